@@ -1,32 +1,110 @@
-use anyhow::{Context, Error, Result};
+use anyhow::{bail, Context, Result};
 use clap::{App, Arg, ArgMatches, SubCommand};
 use csv::{QuoteStyle, WriterBuilder};
-use libangelshark::{Acm, AcmRunner, Message, ParallelIterator};
+use libangelshark::{
+    AcmRunner, EnvSecret, FileSecret, Logins, Message, OssiError, OutputRow, ParallelIterator,
+    RetryPolicy, SecretProvider, Timeouts,
+};
 use std::{
-    fs::File,
+    env,
+    fs::{self, File, OpenOptions},
     io::{stdin, stdout, BufWriter, Write},
+    path::Path,
+    process::{self, Command},
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::Duration,
 };
 
+/// Holds the key for encrypted logins when `--logins-key-file` isn't given.
+const LOGINS_KEY_VAR: &str = "ANGELSHARK_LOGINS_KEY";
+
+/// How many rows of an ACM's output are read ahead while an earlier ACM's
+/// output is printed.
+const BUFFERED_ROWS: usize = 64;
+
 fn main() -> Result<()> {
     // Parse arguments.
     let args = parse_args();
 
-    // Collect logins.
+    // Collect logins, decrypting them if they are encrypted.
     let path = args.value_of("config").unwrap_or("./asa.cfg");
+    let key = logins_key(&args);
+    let key = key.as_deref();
+    match args.subcommand() {
+        ("encrypt", _) => return encrypt_logins(path, key),
+        ("decrypt", _) => return decrypt_logins(path, key),
+        ("edit", _) => return edit_logins(path, key),
+        _ => (),
+    }
     let logins_file =
         File::open(path).with_context(|| format!("Failed to open logins file: {}", path))?;
-    let acms = Acm::from_logins(logins_file).with_context(|| "Failed to parse logins.")?;
+    let mut logins = Logins::from_reader_with_key(logins_file, key)
+        .with_context(|| "Failed to parse logins.")?;
+
+    if let ("validate", _) = args.subcommand() {
+        // Check the logins without reading input or connecting to anything.
+        logins.validate()?;
+        println!(
+            "{}: {} ACM(s), {} group(s)",
+            path,
+            logins.acms.len(),
+            logins.groups.len()
+        );
+        return Ok(());
+    }
+
+    // Apply host key verification and timeout defaults to logins that don't
+    // set their own.
+    let host_key_check = args
+        .value_of("host_key_check")
+        .map(str::parse)
+        .transpose()?;
+    let known_hosts = args.value_of("known_hosts").map(Path::new);
+    let timeouts = parse_timeouts(&args)?;
+    for (_, acm) in &mut logins.acms {
+        acm.with_host_key_defaults(host_key_check, known_hosts)
+            .with_timeout_defaults(timeouts);
+    }
+
+    // Retry runs that fail while logging in, if asked to.
+    let retries: u32 = args
+        .value_of("retries")
+        .unwrap_or("0")
+        .parse()
+        .with_context(|| "Invalid number of retries.")?;
+    let new_runner = |logins: Logins, inputs: Vec<(String, Message)>| {
+        // Groups have to be registered before inputs are queued for them.
+        let mut runner = AcmRunner::new(logins.acms, Vec::new());
+        for (name, members) in logins.groups {
+            runner.register_group(&name, members);
+        }
+        for (name, input) in inputs {
+            runner.queue_input(&name, &input);
+        }
+        if retries > 0 {
+            runner.with_retry(RetryPolicy {
+                max_attempts: retries.saturating_add(1),
+                ..Default::default()
+            });
+        }
+        runner
+    };
+
     let inputs = Message::from_input(stdin()).with_context(|| "Failed to read input.")?;
 
     match args.subcommand() {
         ("test", _) => {
-            // Echo the parsed logins and input.
-            println!("{:?}", acms);
-            println!("{:?}", inputs);
+            // Print what would run where, without logging in to anything.
+            let plan = new_runner(logins, inputs).plan();
+            println!("{}", plan);
+            if !plan.is_valid() {
+                bail!("Dry run found inputs that would not run as written.");
+            }
         }
         ("man", _) => {
             // Print manual pages for the given input.
-            AcmRunner::new(acms, inputs)
+            new_runner(logins, inputs)
                 .manuals()
                 .for_each(|(name, output)| match output {
                     Err(e) => eprintln!("angelsharkcli: manual ({}): {}", name, e),
@@ -34,103 +112,61 @@ fn main() -> Result<()> {
                 });
         }
         ("print", Some(args)) => {
-            // Run the input and print the output.
-            let format = args.value_of("format").unwrap_or("tsv");
-            let header_row = args.is_present("header_row");
-            let prefix = args.value_of("prefix").unwrap_or_default();
-            let to_file = args.is_present("to_file");
-
-            AcmRunner::new(acms, inputs)
-                .run()
-                .filter_map(|(name, output)| match output {
+            // Run the input on every ACM at once. Each ACM writes its own
+            // files as its output arrives. On STDOUT, output is printed in
+            // input order: the first ACM's rows are printed as they arrive,
+            // and later ACMs' rows are read ahead in the background, a few at
+            // a time, until it's their turn.
+            let options = PrintOptions {
+                format: args.value_of("format").unwrap_or("tsv"),
+                header_row: args.is_present("header_row"),
+                prefix: args.value_of("prefix").unwrap_or_default(),
+                to_file: args.is_present("to_file"),
+            };
+
+            let outputs = new_runner(logins, inputs).run_stream();
+            if options.to_file {
+                outputs.try_for_each(|(name, output)| match output {
                     Err(e) => {
                         eprintln!("angelsharkcli: runner ({}): {}", name, e);
-                        None
-                    }
-                    Ok(messages) => Some((name, messages)),
-                })
-                .try_for_each(|(name, outputs)| {
-                    for (command, datas) in outputs.into_iter().filter_map(|message| {
-                        if message.command == "logoff" {
-                            None
-                        } else if let Some(error) = &message.error {
-                            eprintln!("angelsharkcli: ossi ({}): {}", name, error);
-                            None
-                        } else if let Some(datas) = message.datas {
-                            if header_row {
-                                Some((
-                                    message.command,
-                                    vec![message.fields.unwrap_or_default()]
-                                        .into_iter()
-                                        .chain(datas.into_iter())
-                                        .collect(),
-                                ))
-                            } else {
-                                Some((message.command, datas))
-                            }
-                        } else {
-                            None
-                        }
-                    }) {
-                        let writer: BufWriter<Box<dyn Write>> = BufWriter::new(if to_file {
-                            let filename = format!(
-                                "./{}angelshark -- {} -- {}.{}",
-                                prefix, name, command, format
-                            );
-                            let file = File::create(&filename).with_context(|| {
-                                format!("Failed to create output file: {}", filename)
-                            })?;
-                            Box::new(file)
-                        } else {
-                            Box::new(stdout())
-                        });
-
-                        match format {
-                            "json" => {
-                                serde_json::to_writer_pretty(writer, &datas)
-                                    .with_context(|| "Failed to write JSON.")?;
-                            }
-                            "csv" => {
-                                let mut writer = WriterBuilder::new()
-                                    .quote_style(QuoteStyle::Always)
-                                    .from_writer(writer);
-
-                                for data in datas {
-                                    writer
-                                        .write_record(&data)
-                                        .with_context(|| "Failed to write CSV.")?;
-                                }
-                            }
-                            _ => {
-                                let mut writer = WriterBuilder::new()
-                                    .delimiter(b'\t')
-                                    .quote_style(QuoteStyle::Never)
-                                    .from_writer(writer);
-
-                                for data in datas {
-                                    writer
-                                        .write_record(&data)
-                                        .with_context(|| "Failed to write TSV.")?;
-                                }
-                            }
-                        }
+                        Ok(())
                     }
-
-                    Result::<(), Error>::Ok(())
+                    Ok(rows) => print_rows(&name, rows, &options),
                 })?;
+            } else {
+                let outputs: Vec<_> = outputs
+                    .map(|(name, output)| (name, output.map(buffer_rows)))
+                    .collect();
+                for (name, output) in outputs {
+                    match output {
+                        Err(e) => eprintln!("angelsharkcli: runner ({}): {}", name, e),
+                        Ok(rows) => print_rows(&name, rows.into_iter(), &options)?,
+                    }
+                }
+            }
         }
         _ => {
-            // Just run the input and print any errors encountered.
-            AcmRunner::new(acms, inputs)
-                .run()
-                .for_each(|(name, output)| match output {
-                    Err(e) => {
-                        eprintln!("angelsharkcli: runner ({}): {}", name, e);
+            // Just run the input and print any errors encountered, and a
+            // report of each run if asked to.
+            let report = args.is_present("report");
+            new_runner(logins, inputs)
+                .run_reports()
+                .for_each(|(name, run)| {
+                    if report {
+                        eprintln!("angelsharkcli: report ({}): {}", name, run);
+                        for command in &run.commands {
+                            eprintln!("angelsharkcli: report ({}): {}", name, command);
+                        }
                     }
-                    Ok(o) => {
-                        for msg in o {
-                            if let Some(e) = msg.error {
-                                eprintln!("angelsharkcli: ossi ({}): {}", name, e);
+                    match run.into_outputs() {
+                        Err(e) => {
+                            eprintln!("angelsharkcli: runner ({}): {}", name, e);
+                        }
+                        Ok(o) => {
+                            for msg in o {
+                                if let Some(e) = msg.error {
+                                    print_ossi_error(&name, &e);
+                                }
                             }
                         }
                     }
@@ -141,6 +177,313 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+/// Prints an OSSI error on STDERR, broken down into its parts if it can be
+/// parsed.
+fn print_ossi_error(name: &str, error: &str) {
+    match error.parse::<OssiError>() {
+        Ok(e) => eprintln!(
+            "angelsharkcli: ossi ({}): {} {:04x} (field {}, entry {}): {}",
+            name,
+            e.kind().as_str(),
+            e.code,
+            e.field,
+            e.index,
+            e.text
+        ),
+        Err(_) => eprintln!("angelsharkcli: ossi ({}): {}", name, error),
+    }
+}
+
+/// Output settings for the `print` subcommand.
+struct PrintOptions<'a> {
+    format: &'a str,
+    header_row: bool,
+    prefix: &'a str,
+    to_file: bool,
+}
+
+/// Writes the data entries of one ACM's output rows as they are read. Each
+/// command with data gets its own writer (and file, if writing to files).
+/// Commands that return errors are reported on STDERR and skipped.
+fn print_rows(
+    name: &str,
+    rows: impl Iterator<Item = Result<OutputRow>>,
+    options: &PrintOptions,
+) -> Result<()> {
+    let mut command = String::new();
+    let mut fields = Vec::new();
+    let mut skip = false;
+    let mut writer: Option<RowWriter> = None;
+
+    for row in rows {
+        let row = match row {
+            Err(e) => {
+                eprintln!("angelsharkcli: runner ({}): {}", name, e);
+                break;
+            }
+            Ok(row) => row,
+        };
+
+        match row {
+            OutputRow::Command(c) => {
+                skip = c == "logoff";
+                command = c;
+                fields.clear();
+            }
+            OutputRow::Error(e) => {
+                print_ossi_error(name, &e);
+                skip = true;
+            }
+            OutputRow::Fields(f) => {
+                fields.extend(f);
+            }
+            OutputRow::Data(data) if !skip => {
+                let writer = match writer.as_mut() {
+                    Some(writer) => writer,
+                    None => {
+                        let mut new = RowWriter::new(name, &command, options)?;
+                        if options.header_row {
+                            new.write(&fields)?;
+                        }
+                        writer.insert(new)
+                    }
+                };
+                writer.write(&data)?;
+            }
+            OutputRow::Data(_) => {}
+            OutputRow::End => {
+                if let Some(writer) = writer.take() {
+                    writer.finish()?;
+                }
+                skip = false;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads `rows` on a new thread as they arrive, buffering up to
+/// [BUFFERED_ROWS] of them until they are received. Stops reading if the
+/// receiver is dropped.
+fn buffer_rows<T: Send + 'static>(rows: impl Iterator<Item = T> + Send + 'static) -> Receiver<T> {
+    let (sender, receiver) = mpsc::sync_channel(BUFFERED_ROWS);
+    thread::spawn(move || {
+        for row in rows {
+            if sender.send(row).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+/// Writes data entries for one command in the chosen format. TSV and CSV
+/// entries are written (and flushed to STDOUT) as soon as they are read. JSON
+/// entries are collected and written as one array when the command finishes.
+enum RowWriter {
+    Delimited {
+        writer: Box<csv::Writer<BufWriter<Box<dyn Write>>>>,
+        flush: bool,
+    },
+    Json {
+        writer: BufWriter<Box<dyn Write>>,
+        datas: Vec<Vec<String>>,
+    },
+}
+
+impl RowWriter {
+    fn new(name: &str, command: &str, options: &PrintOptions) -> Result<Self> {
+        let writer: BufWriter<Box<dyn Write>> = BufWriter::new(if options.to_file {
+            let filename = format!(
+                "./{}angelshark -- {} -- {}.{}",
+                options.prefix, name, command, options.format
+            );
+            let file = File::create(&filename)
+                .with_context(|| format!("Failed to create output file: {}", filename))?;
+            Box::new(file)
+        } else {
+            Box::new(stdout())
+        });
+
+        Ok(match options.format {
+            "json" => Self::Json {
+                writer,
+                datas: Vec::new(),
+            },
+            "csv" => Self::Delimited {
+                writer: Box::new(
+                    WriterBuilder::new()
+                        .quote_style(QuoteStyle::Always)
+                        .from_writer(writer),
+                ),
+                flush: !options.to_file,
+            },
+            _ => Self::Delimited {
+                writer: Box::new(
+                    WriterBuilder::new()
+                        .delimiter(b'\t')
+                        .quote_style(QuoteStyle::Never)
+                        .from_writer(writer),
+                ),
+                flush: !options.to_file,
+            },
+        })
+    }
+
+    fn write(&mut self, data: &[String]) -> Result<()> {
+        match self {
+            Self::Delimited { writer, flush } => {
+                writer
+                    .write_record(data)
+                    .with_context(|| "Failed to write record.")?;
+                if *flush {
+                    writer.flush().with_context(|| "Failed to flush record.")?;
+                }
+            }
+            Self::Json { datas, .. } => {
+                datas.push(data.to_vec());
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<()> {
+        match self {
+            Self::Delimited { mut writer, .. } => {
+                writer.flush().with_context(|| "Failed to write record.")
+            }
+            Self::Json { writer, datas } => serde_json::to_writer_pretty(writer, &datas)
+                .with_context(|| "Failed to write JSON."),
+        }
+    }
+}
+
+/// Reads the timeout options, keeping the defaults for any that are not set.
+fn parse_timeouts(args: &ArgMatches) -> Result<Timeouts> {
+    let seconds = |name: &str| -> Result<Option<Duration>> {
+        args.value_of(name)
+            .map(|value| {
+                value
+                    .parse()
+                    .ok()
+                    .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                    .with_context(|| format!("Invalid number of seconds for {}: {}", name, value))
+            })
+            .transpose()
+    };
+
+    let mut timeouts = Timeouts::default();
+    if let Some(connect) = seconds("connect_timeout")? {
+        timeouts.connect = connect;
+    }
+    if let Some(login) = seconds("login_timeout")? {
+        timeouts.login = login;
+    }
+    if let Some(command) = seconds("command_timeout")? {
+        timeouts.command = command;
+    }
+    timeouts.run = seconds("run_timeout")?;
+    Ok(timeouts)
+}
+
+/// Finds the key for encrypted logins in the `--logins-key-file` file or, if
+/// that isn't given, the [LOGINS_KEY_VAR] environment variable.
+fn logins_key(args: &ArgMatches) -> Option<Box<dyn SecretProvider>> {
+    match args.value_of("logins_key_file") {
+        Some(path) => Some(Box::new(FileSecret { path: path.into() })),
+        None if env::var_os(LOGINS_KEY_VAR).is_some() => Some(Box::new(EnvSecret {
+            name: LOGINS_KEY_VAR.into(),
+        })),
+        None => None,
+    }
+}
+
+fn passphrase(key: Option<&dyn SecretProvider>) -> Result<String> {
+    key.with_context(|| {
+        format!(
+            "No key for encrypted logins. Set {} or --logins-key-file.",
+            LOGINS_KEY_VAR
+        )
+    })?
+    .secret()
+}
+
+fn read_logins(path: &str) -> Result<Vec<u8>> {
+    fs::read(path).with_context(|| format!("Failed to open logins file: {}", path))
+}
+
+/// Replaces the login file at `path` with `contents`, only readable by the
+/// current user.
+fn write_logins(path: &str, contents: &[u8]) -> Result<()> {
+    let temp = format!("{}.tmp", path);
+    write_private(Path::new(&temp), contents)?;
+    fs::rename(&temp, path).with_context(|| format!("Failed to replace logins file: {}", path))
+}
+
+fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(contents))
+        .with_context(|| format!("Failed to write file: {}", path.display()))
+}
+
+/// Encrypts the plaintext login file at `path` in place, once it's known to
+/// parse.
+fn encrypt_logins(path: &str, key: Option<&dyn SecretProvider>) -> Result<()> {
+    let contents = read_logins(path)?;
+    if Logins::is_encrypted(&contents) {
+        bail!("Logins file is already encrypted: {}", path);
+    }
+    Logins::from_reader(contents.as_slice()).with_context(|| "Failed to parse logins.")?;
+    write_logins(path, &Logins::encrypt(&contents, &passphrase(key)?)?)
+}
+
+/// Prints the plaintext of the encrypted login file at `path`.
+fn decrypt_logins(path: &str, key: Option<&dyn SecretProvider>) -> Result<()> {
+    let contents = Logins::decrypt(&read_logins(path)?, &passphrase(key)?)?;
+    stdout()
+        .write_all(&contents)
+        .with_context(|| "Failed to write decrypted logins.")
+}
+
+/// Opens a decrypted copy of the encrypted login file at `path` in `$VISUAL`
+/// or `$EDITOR` (`vi` if neither is set), then encrypts the edited copy back
+/// in place if it parses. The copy is removed either way.
+fn edit_logins(path: &str, key: Option<&dyn SecretProvider>) -> Result<()> {
+    let passphrase = passphrase(key)?;
+    let contents = Logins::decrypt(&read_logins(path)?, &passphrase)?;
+    let copy = env::temp_dir().join(format!("angelsharkcli-{}.cfg", process::id()));
+    write_private(&copy, &contents)?;
+
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| String::from("vi"));
+    let mut editor = editor.split_whitespace();
+    let edited = Command::new(editor.next().unwrap_or("vi"))
+        .args(editor)
+        .arg(&copy)
+        .status()
+        .with_context(|| "Failed to start editor.")
+        .and_then(|status| match status.success() {
+            true => fs::read(&copy).with_context(|| "Failed to read edited logins."),
+            false => bail!(
+                "Editor exited with {}. The login file was not changed.",
+                status
+            ),
+        });
+    fs::remove_file(&copy).with_context(|| "Failed to remove decrypted copy of logins.")?;
+    let edited = edited?;
+
+    Logins::from_reader(edited.as_slice())
+        .with_context(|| "Failed to parse edited logins. The login file was not changed.")?;
+    write_logins(path, &Logins::encrypt(&edited, &passphrase)?)
+}
+
 fn parse_args() -> ArgMatches<'static> {
     let app = App::new("Altruistic Angelshark CLI")
         .author(env!("CARGO_PKG_AUTHORS"))
@@ -148,8 +491,21 @@ fn parse_args() -> ArgMatches<'static> {
         .version(env!("CARGO_PKG_VERSION"))
         .long_about("\nReads STDIN and parses all lines as commands to be fed to one or more ACMs. When it reaches EOF, it stops parsing and starts executing the command(s) on the ACM(s). What it does with the output can be configured with subcommands and flags. If you like keeping your commands in a file, consider using the `<` to read it on STDIN. The default behavior is to run commands but print no output (for quick changes). Errors are printed on STDERR.")
         .arg(Arg::with_name("config").long("login-file").short("l").default_value("./asa.cfg").help("Set ACM login configuration file"))
-        .subcommand(SubCommand::with_name("test").about("Prints parsed logins and inputs but does not run anything").long_about("Does not execute commands entered, instead prints out the ACM logins and inputs it read (useful for debugging)"))
+        .arg(Arg::with_name("logins_key_file").long("logins-key-file").takes_value(true).help("Read the key for an encrypted login file from a file [default: $ANGELSHARK_LOGINS_KEY]"))
+        .arg(Arg::with_name("host_key_check").long("host-key-check").short("k").takes_value(true).possible_values(&["strict", "tofu", "off"]).help("Set how ACM host keys are verified when a login does not specify it [default: tofu]"))
+        .arg(Arg::with_name("known_hosts").long("known-hosts").takes_value(true).help("Set known_hosts file for logins that do not specify one [default: ./known_hosts]"))
+        .arg(Arg::with_name("connect_timeout").long("connect-timeout").takes_value(true).help("Set seconds to wait for a TCP connection to each ACM address [default: 30]"))
+        .arg(Arg::with_name("login_timeout").long("login-timeout").takes_value(true).help("Set seconds to wait for SSH login and the OSSI prompt [default: 30]"))
+        .arg(Arg::with_name("command_timeout").long("command-timeout").takes_value(true).help("Set seconds to wait for each command's output [default: 30]"))
+        .arg(Arg::with_name("retries").long("retries").takes_value(true).help("Set how many times to retry an ACM that can't be connected or logged in to [default: 0]"))
+        .arg(Arg::with_name("report").long("report").short("r").help("Print how long each ACM's login and commands took, and bytes sent and received, on STDERR (when running without a subcommand)"))
+        .arg(Arg::with_name("run_timeout").long("run-timeout").takes_value(true).help("Set seconds an entire run on one ACM may take [default: none]"))
+        .subcommand(SubCommand::with_name("test").about("Prints a plan of what would run on which ACM but does not run anything").long_about("Dry run. Does not execute commands entered, instead prints which inputs would run on which ACM login, flagging commands that would change an ACM (any but display, list, monitor, or status). Fails if an input names an ACM that is not in the login file or requests an invalid field ID (not eight hexadecimal digits)."))
+        .subcommand(SubCommand::with_name("validate").about("Checks the login file for errors without connecting to anything").long_about("Parses the login file, failing on malformed lines, duplicate ACM or group names, and group members that match no ACM. Errors name the line of the problem. Does not read input or connect to any ACM."))
+        .subcommand(SubCommand::with_name("encrypt").about("Encrypts the login file in place").long_about("Encrypts the plaintext login file in place with the key from --logins-key-file or $ANGELSHARK_LOGINS_KEY, once it parses. Encrypted login files are decrypted automatically on every run."))
+        .subcommand(SubCommand::with_name("decrypt").about("Prints the decrypted login file to STDOUT").long_about("Decrypts the encrypted login file with the key from --logins-key-file or $ANGELSHARK_LOGINS_KEY and prints it on STDOUT. The file itself stays encrypted."))
+        .subcommand(SubCommand::with_name("edit").about("Edits the encrypted login file in $VISUAL or $EDITOR").long_about("Decrypts the encrypted login file to a temporary copy only readable by the current user, opens it in $VISUAL or $EDITOR (vi if neither is set), then encrypts the edited copy back in place if it parses. The copy is removed afterward."))
         .subcommand(SubCommand::with_name("man").about("Prints command manual pages via `ossim` term").long_about("Reads commands on STDIN and prints their SAT manual pages on STDOUT"))
-        .subcommand(SubCommand::with_name("print").about("Prints command output to STDOUT (or files) in a useful format").long_about("Runs commands on input and writes *their data entries* to STDOUT in variety of formats (and optionally to files)").arg(Arg::with_name("prefix").long("prefix").short("p").takes_value(true).requires("to_file").help("Prepend a prefix to all output filenames")).arg(Arg::with_name("to_file").short("t").long("to-file").help("Write output to separate files instead of STDOUT")).arg(Arg::with_name("header_row").short("h").long("header-row").help("Prepend header entry of hexadecimal field addresses to output")).arg(Arg::with_name("format").short("f").long("format").possible_values(&["csv", "json", "tsv"]).default_value("tsv").help("Format data should be printed in")));
+        .subcommand(SubCommand::with_name("print").about("Prints command output to STDOUT (or files) in a useful format").long_about("Runs commands on input and writes *their data entries* to STDOUT in variety of formats (and optionally to files). Output is printed in input order, ACM by ACM. TSV and CSV entries are written as soon as they are read from the ACM.").arg(Arg::with_name("prefix").long("prefix").short("p").takes_value(true).requires("to_file").help("Prepend a prefix to all output filenames")).arg(Arg::with_name("to_file").short("t").long("to-file").help("Write output to separate files instead of STDOUT")).arg(Arg::with_name("header_row").short("h").long("header-row").help("Prepend header entry of hexadecimal field addresses to output")).arg(Arg::with_name("format").short("f").long("format").possible_values(&["csv", "json", "tsv"]).default_value("tsv").help("Format data should be printed in")));
     app.get_matches_safe().unwrap_or_else(|e| e.exit())
 }
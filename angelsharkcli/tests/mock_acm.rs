@@ -0,0 +1,185 @@
+use angelsharkmock::{MockAcm, MockServer, TempFile};
+use std::{
+    env, fs,
+    io::Write,
+    process::{self, Command, Output, Stdio},
+};
+
+/// Runs the CLI with a logins file for `server` named CM01, feeding it `input`
+/// on STDIN.
+fn angelsharkcli(server: &MockServer, args: &[&str], input: &str) -> Output {
+    let logins = server.logins_file("CM01").unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_angelsharkcli"))
+        .arg("-l")
+        .arg(logins.path())
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn print_writes_tsv() {
+    let server = MockAcm::stations().start().unwrap();
+    let output = angelsharkcli(
+        &server,
+        &["print", "--header-row"],
+        "aCM01\nclist station\nt\n",
+    );
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "8005ff00\t8003ff00\n17571230000\tCarpenter, Adam\n17571230001\tDoe, Jane\n"
+    );
+}
+
+#[test]
+fn print_to_file_writes_every_acm() {
+    let servers = [
+        MockAcm::stations().start().unwrap(),
+        MockAcm::stations().start().unwrap(),
+    ];
+    let logins = TempFile::new(
+        "to-file.cfg",
+        format!(
+            "{}\n{}\n",
+            servers[0].login_line("CM01"),
+            servers[1].login_line("CM02")
+        ),
+    )
+    .unwrap();
+    let dir = env::temp_dir().join(format!("angelsharkcli-{}-to-file", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_angelsharkcli"))
+        .arg("-l")
+        .arg(logins.path())
+        .args(["print", "--to-file"])
+        .current_dir(&dir)
+        .stdin(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"aCM0*\nclist station\nf8003ff00\nt\n")
+        .unwrap();
+    let status = child.wait().unwrap();
+    let read = |name: &str| {
+        fs::read_to_string(dir.join(format!("angelshark -- {} -- list station.tsv", name)))
+    };
+    let (first, second) = (read("CM01"), read("CM02"));
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(status.success());
+    assert_eq!(first.unwrap(), "Carpenter, Adam\nDoe, Jane\n");
+    assert_eq!(second.unwrap(), "Carpenter, Adam\nDoe, Jane\n");
+}
+
+#[test]
+fn ossi_errors_are_reported() {
+    let server = MockAcm::stations().start().unwrap();
+    let output = angelsharkcli(&server, &[], "aCM01\ncdisplay station 17571239999\nt\n");
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "angelsharkcli: ossi (CM01): not-found 29cf (field 00000000, entry 1): No records match the specified query options\n"
+    );
+}
+
+#[test]
+fn test_is_a_dry_run() {
+    let server = MockAcm::stations().start().unwrap();
+    let output = angelsharkcli(&server, &["test"],
+        "aCM01\nclist station\nf8005ff00\nt\naCM01\ncchange station 17571230000\nt\naCM9\nclist station\nt\n",
+    );
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!output.status.success());
+    assert!(stdout.contains("    list station [fields: 8005ff00]\n"));
+    assert!(stdout.contains("    change station 17571230000 [changes ACM]\n"));
+    assert!(stdout.contains("CM9 (unknown ACM)\n    list station\n"));
+    assert_eq!(server.logins(), 0);
+}
+
+#[test]
+fn unknown_acms_are_reported() {
+    let server = MockAcm::stations().start().unwrap();
+    let output = angelsharkcli(&server, &[], "aCM1\nclist station\nt\n");
+
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "angelsharkcli: runner (CM1): No ACM is configured as 'CM1'. Did you mean 'CM01'?\n"
+    );
+    assert_eq!(server.logins(), 0);
+}
+
+#[test]
+fn validate_reports_bad_lines() {
+    let logins = TempFile::new("validate.cfg", "").unwrap();
+    let validate = |config: &str| {
+        fs::write(logins.path(), config).unwrap();
+        Command::new(env!("CARGO_BIN_EXE_angelsharkcli"))
+            .arg("-l")
+            .arg(logins.path())
+            .arg("validate")
+            .stdin(Stdio::null())
+            .output()
+            .unwrap()
+    };
+
+    let valid = validate("# East\nCM01 mock:mock@127.0.0.1:5022\n\n@east = CM0*\n");
+    let malformed = validate("CM01 mock:mock@127.0.0.1:5022\nCM02 mock:mock\n");
+    let duplicate = validate("CM01 mock:mock@127.0.0.1\nCM01 mock:mock@127.0.0.2\n");
+
+    assert!(valid.status.success());
+    assert!(String::from_utf8_lossy(&valid.stdout).ends_with(": 1 ACM(s), 1 group(s)\n"));
+    assert!(String::from_utf8_lossy(&malformed.stderr).contains("Invalid login at line 2."));
+    assert!(String::from_utf8_lossy(&duplicate.stderr)
+        .contains("Duplicate name CM01 at line 2 (first defined at line 1)."));
+}
+
+#[test]
+fn encrypted_logins_are_read_transparently() {
+    let logins = TempFile::new("encrypted.cfg", "CM01 mock:mock@127.0.0.1\n").unwrap();
+    let run = |key: &str, args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_angelsharkcli"))
+            .arg("-l")
+            .arg(logins.path())
+            .args(args)
+            .env("ANGELSHARK_LOGINS_KEY", key)
+            .env_remove("VISUAL")
+            .env("EDITOR", "sed -i s/CM01/CM02/")
+            .stdin(Stdio::null())
+            .output()
+            .unwrap()
+    };
+
+    assert!(run("secret", &["encrypt"]).status.success());
+    let encrypted = fs::read(logins.path()).unwrap();
+    let validated = run("secret", &["validate"]);
+    let wrong_key = run("wrong", &["validate"]);
+    assert!(run("secret", &["edit"]).status.success());
+    let decrypted = run("secret", &["decrypt"]);
+
+    assert!(!String::from_utf8_lossy(&encrypted).contains("mock"));
+    assert!(validated.status.success());
+    assert!(String::from_utf8_lossy(&wrong_key.stderr).contains("Failed to decrypt logins."));
+    assert_eq!(
+        String::from_utf8_lossy(&decrypted.stdout),
+        "CM02 mock:mock@127.0.0.1\n"
+    );
+}
@@ -0,0 +1,180 @@
+use crate::routes::auth::Caller;
+use anyhow::{Context, Result};
+use libangelshark::{Message, RunOutput};
+use log::error;
+use serde::Serialize;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind, Write},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+/// An append-only audit trail of every OSSI command run through the daemon,
+/// written as JSON lines. Once the file would grow past its maximum size, it
+/// is renamed with a `.1` suffix (older files shifting to `.2` and so on) and
+/// a new one is started.
+#[derive(Clone)]
+pub struct AuditLog(Arc<Mutex<Writer>>);
+
+struct Writer {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    keep: usize,
+}
+
+/// One line of the audit log: one input run on one ACM.
+#[derive(Serialize)]
+struct Entry<'a> {
+    time: String,
+    key: Option<&'a str>,
+    addr: Option<IpAddr>,
+    route: &'a str,
+    acm: &'a str,
+    command: &'a str,
+    fields: &'a [String],
+    datas: &'a [Vec<String>],
+    /// The OSSI error, or why the input never ran. `None` if it succeeded.
+    error: Option<&'a str>,
+}
+
+impl AuditLog {
+    /// Opens the audit log at `path` for appending, creating it if needed.
+    /// Keeps `keep` rotated files of up to `max_size` bytes.
+    pub fn open(path: impl AsRef<Path>, max_size: u64, keep: usize) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = open_append(&path)
+            .with_context(|| format!("Failed to open audit log: {}", path.display()))?;
+        let size = file.metadata()?.len();
+        Ok(Self(Arc::new(Mutex::new(Writer {
+            path,
+            file,
+            size,
+            max_size,
+            keep,
+        }))))
+    }
+
+    /// Records every input of `outputs`, which `caller` ran through `route`,
+    /// with its outcome. `inputs` are the inputs queued for each ACM, after
+    /// expanding groups and patterns, in order.
+    pub fn record_outputs(
+        &self,
+        caller: &Caller,
+        route: &str,
+        inputs: &[(String, Message)],
+        outputs: &[RunOutput],
+    ) {
+        for (acm, output) in outputs {
+            let (outputs, error) = match output {
+                Ok(outputs) => (outputs.as_slice(), None),
+                Err(e) => (&[][..], Some(e.to_string())),
+            };
+            self.record_job(caller, route, acm, inputs, outputs, error.as_deref());
+        }
+    }
+
+    /// Records each of the inputs queued for `acm` with its output, in order.
+    /// Inputs without an output are recorded with `error`, or as never having
+    /// an output.
+    pub fn record_job(
+        &self,
+        caller: &Caller,
+        route: &str,
+        acm: &str,
+        inputs: &[(String, Message)],
+        outputs: &[Message],
+        error: Option<&str>,
+    ) {
+        let mut outputs = outputs.iter().filter(|o| o.command != "logoff");
+        for (_, input) in inputs.iter().filter(|(name, _)| name == acm) {
+            let error = match outputs.next() {
+                Some(output) => output.error.as_deref(),
+                None => Some(error.unwrap_or("No output.")),
+            };
+            self.record(caller, route, acm, input, error);
+        }
+    }
+
+    /// Records `input`, which `caller` ran (or tried to run) on `acm` through
+    /// `route`, with its error, if any. Failures to write are logged, never
+    /// returned, so that auditing can't take down a request.
+    pub fn record(
+        &self,
+        caller: &Caller,
+        route: &str,
+        acm: &str,
+        input: &Message,
+        error: Option<&str>,
+    ) {
+        let entry = Entry {
+            time: humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+            key: caller.key.as_deref(),
+            addr: caller.addr,
+            route,
+            acm,
+            command: &input.command,
+            fields: input.fields.as_deref().unwrap_or_default(),
+            datas: input.datas.as_deref().unwrap_or_default(),
+            error,
+        };
+        let mut line = serde_json::to_vec(&entry).unwrap_or_default();
+        line.push(b'\n');
+
+        let written = match self.0.lock() {
+            Ok(mut writer) => writer.write_line(&line),
+            Err(e) => Err(io::Error::other(e.to_string())),
+        };
+        if let Err(e) = written {
+            error!("Failed to write audit log: {}", e);
+        }
+    }
+}
+
+impl Writer {
+    fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        self.file.write_all(line)?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    /// Shifts every rotated file up one number, dropping the oldest, and
+    /// starts a new file.
+    fn rotate(&mut self) -> io::Result<()> {
+        let rotated = |n: usize| {
+            let mut path = self.path.clone().into_os_string();
+            path.push(format!(".{}", n));
+            PathBuf::from(path)
+        };
+        for n in (1..self.keep).rev() {
+            match fs::rename(rotated(n), rotated(n + 1)) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        if self.keep > 0 {
+            fs::rename(&self.path, rotated(1))?;
+        } else {
+            fs::remove_file(&self.path)?;
+        }
+        self.file = open_append(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+/// Opens `path` for appending, readable only by its owner.
+fn open_append(path: &Path) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.append(true).create(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)
+}
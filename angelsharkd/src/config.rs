@@ -1,27 +1,49 @@
-use anyhow::{Context, Result};
-use libangelshark::{Acm, AcmRunner};
+use crate::{
+    audit::AuditLog,
+    routes::{auth::ApiKeys, policy::Policy},
+    tls::TlsPaths,
+};
+use anyhow::{bail, Context, Result};
+use libangelshark::{
+    AcmRunner, CancelToken, EnvSecret, Executor, FileSecret, Logins, RetryPolicy, SecretProvider,
+    SessionPool, Timeouts,
+};
 use std::{
     env,
     fs::File,
     net::{Ipv4Addr, SocketAddrV4},
+    path::PathBuf,
+    str::FromStr,
+    time::Duration,
 };
 
 #[derive(Clone)]
 pub struct Config {
     pub bind_addr: SocketAddrV4,
     pub debug_mode: bool,
+    pub read_only: bool,
     pub runner: AcmRunner,
+    pub pool: Option<SessionPool>,
+    pub cancel: CancelToken,
     pub origin: String,
+    pub api_keys: Option<ApiKeys>,
+    pub policy: Option<Policy>,
+    pub audit: Option<AuditLog>,
+    pub tls: Option<TlsPaths>,
 }
 
 impl Config {
     pub fn init() -> Result<Self> {
         let debug_mode = cfg!(debug_assertions) || env::var_os("ANGELSHARKD_DEBUG").is_some();
+        let read_only = env::var_os("ANGELSHARKD_READ_ONLY").is_some();
+
+        let bind_addr = env_or(
+            "ANGELSHARKD_ADDR",
+            "socket bind address",
+            SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 8080),
+        )?;
 
-        let bind_addr: SocketAddrV4 = env::var("ANGELSHARKD_ADDR")
-            .map(|addr| addr.parse())
-            .unwrap_or_else(|_| Ok(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 8080)))
-            .with_context(|| "Failed to parse socket bind address.")?;
+        let tls = TlsPaths::from_env()?;
 
         let origin = if debug_mode {
             String::new()
@@ -31,25 +53,139 @@ impl Config {
             })?
         };
 
+        let api_keys = match env::var_os("ANGELSHARKD_API_KEYS") {
+            Some(path) => Some(ApiKeys::from_file(path)?),
+            None if debug_mode => None,
+            None => bail!("In release mode, API keys are required. Set ANGELSHARKD_API_KEYS"),
+        };
+
+        let policy = env::var_os("ANGELSHARKD_POLICY")
+            .map(Policy::from_file)
+            .transpose()?;
+        if let Some(policy) = &policy {
+            let keys = api_keys.as_ref().with_context(|| {
+                "A policy gives roles to API keys, which are not set. Set ANGELSHARKD_API_KEYS"
+            })?;
+            if let Some((key, role)) = policy.keys().find(|(key, _)| !keys.contains(key)) {
+                bail!("Unknown API key {} in policy role {}.", key, role);
+            }
+        }
+
+        let audit_max_size = env_or(
+            "ANGELSHARKD_AUDIT_MAX_SIZE",
+            "audit log max size",
+            10 * 1024 * 1024,
+        )?;
+        let audit_keep = env_or("ANGELSHARKD_AUDIT_KEEP", "number of audit logs to keep", 10)?;
+        let audit = env::var_os("ANGELSHARKD_AUDIT_LOG")
+            .map(|path| AuditLog::open(path, audit_max_size, audit_keep))
+            .transpose()?;
+
         let logins = if let Ok(path) = env::var("ANGELSHARKD_LOGINS") {
             File::open(path)
         } else {
             File::open("./asa.cfg")
         }
         .with_context(|| "Failed to open logins file.")?;
+        let logins_key: Option<Box<dyn SecretProvider>> =
+            match env::var_os("ANGELSHARKD_LOGINS_KEY_FILE") {
+                Some(path) => Some(Box::new(FileSecret { path: path.into() })),
+                None if env::var_os("ANGELSHARKD_LOGINS_KEY").is_some() => {
+                    Some(Box::new(EnvSecret {
+                        name: "ANGELSHARKD_LOGINS_KEY".into(),
+                    }))
+                }
+                None => None,
+            };
+
+        let host_key_check = env_parse("ANGELSHARKD_HOST_KEY_CHECK", "host key check mode")?;
+        let known_hosts = env::var_os("ANGELSHARKD_KNOWN_HOSTS").map(PathBuf::from);
+
+        let pool_size = env_or("ANGELSHARKD_POOL_SIZE", "session pool size", 2)?;
+        let pool_idle = env_or("ANGELSHARKD_POOL_IDLE", "session pool idle time", 300)?;
+        let pool =
+            (pool_size > 0).then(|| SessionPool::new(pool_size, Duration::from_secs(pool_idle)));
+
+        let mut timeouts = Timeouts::default();
+        if let Some(connect) = env_secs("ANGELSHARKD_CONNECT_TIMEOUT")? {
+            timeouts.connect = connect;
+        }
+        if let Some(login) = env_secs("ANGELSHARKD_LOGIN_TIMEOUT")? {
+            timeouts.login = login;
+        }
+        if let Some(command) = env_secs("ANGELSHARKD_COMMAND_TIMEOUT")? {
+            timeouts.command = command;
+        }
+        timeouts.run = env_secs("ANGELSHARKD_RUN_TIMEOUT")?;
+        let cancel = CancelToken::default();
+        let retries: u32 = env_or("ANGELSHARKD_RETRIES", "number of retries", 0)?;
+        let max_parallel = env_or("ANGELSHARKD_MAX_PARALLEL", "max parallel runs", 16)?;
+        let max_per_acm = env_or("ANGELSHARKD_MAX_PER_ACM", "max runs per ACM", 4)?;
 
         let mut runner = AcmRunner::default();
-        for (job_name, acm) in
-            Acm::from_logins(logins).with_context(|| "Failed to parse logins.")?
-        {
+        runner
+            .with_cancel(cancel.clone())
+            .with_executor(Executor::new(max_parallel, max_per_acm)?);
+        if retries > 0 {
+            runner.with_retry(RetryPolicy {
+                max_attempts: retries.saturating_add(1),
+                ..Default::default()
+            });
+        }
+        if let Some(pool) = &pool {
+            runner.with_pool(pool.clone());
+        }
+        let logins = Logins::from_reader_with_key(logins, logins_key.as_deref())
+            .with_context(|| "Failed to parse logins.")?;
+        for (job_name, mut acm) in logins.acms {
+            acm.with_host_key_defaults(host_key_check, known_hosts.as_deref())
+                .with_timeout_defaults(timeouts);
             runner.register_acm(&job_name, acm);
         }
+        for (name, members) in logins.groups {
+            runner.register_group(&name, members);
+        }
 
         Ok(Self {
             bind_addr,
             origin,
             debug_mode,
+            read_only,
             runner,
+            pool,
+            cancel,
+            api_keys,
+            policy,
+            audit,
+            tls,
         })
     }
 }
+
+/// Parses the environment variable `name`, if set. `what` describes it in
+/// errors.
+fn env_parse<T>(name: &str, what: &str) -> Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: Into<anyhow::Error>,
+{
+    env::var(name)
+        .ok()
+        .map(|value| value.parse().map_err(Into::into))
+        .transpose()
+        .with_context(|| format!("Failed to parse {}.", what))
+}
+
+/// Like [env_parse], but returns `default` if the variable is not set.
+fn env_or<T>(name: &str, what: &str, default: T) -> Result<T>
+where
+    T: FromStr,
+    T::Err: Into<anyhow::Error>,
+{
+    Ok(env_parse(name, what)?.unwrap_or(default))
+}
+
+/// Reads a number of seconds from the environment variable `name`, if set.
+fn env_secs(name: &str) -> Result<Option<Duration>> {
+    Ok(env_parse(name, &format!("{} as seconds", name))?.map(Duration::from_secs))
+}
@@ -1,11 +1,18 @@
 use crate::config::Config;
 use anyhow::{Context, Result};
 use log::{debug, error, info, LevelFilter};
-use tokio::{signal, task};
-use warp::{hyper::Method, Filter};
+use std::time::Duration;
+use tls::Hangups;
+use tokio::{signal, sync::oneshot, task, time};
+use warp::{
+    hyper::{header, Method},
+    Filter,
+};
 
+mod audit;
 mod config;
 mod routes;
+mod tls;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -27,32 +34,114 @@ async fn main() -> Result<()> {
     if config.debug_mode {
         debug!("**** DEBUGGING MODE ENABLED ****");
     }
+    if config.read_only {
+        info!("Read-only mode enabled. Commands that may change an ACM will be refused.");
+    }
+
+    // Periodically log off idle pooled sessions.
+    if let Some(pool) = config.pool.clone() {
+        task::spawn(async move {
+            let mut interval = time::interval(Duration::from_secs(60));
+            loop {
+                interval.tick().await;
+                let pool = pool.clone();
+                if let Err(e) = task::spawn_blocking(move || pool.prune()).await {
+                    error!("Failed to prune session pool: {}", e);
+                }
+            }
+        });
+    }
 
-    let routes = routes::index()
+    // Everything but the version route needs an API key.
+    let routes = routes::index(&config)
         .or(routes::ossi(&config))
         .or(routes::extensions::filter(&config))
+        .recover(routes::auth::handle_rejection)
         .with(if config.debug_mode || config.origin == "*" {
             warp::cors()
                 .allow_any_origin()
                 .allow_methods(&[Method::GET, Method::POST])
+                .allow_header(header::AUTHORIZATION)
         } else {
             warp::cors()
                 .allow_origin(config.origin.as_str())
                 .allow_methods(&[Method::GET, Method::POST])
+                .allow_header(header::AUTHORIZATION)
         })
         .with(warp::log("angelsharkd"));
 
-    // Create server with shutdown signal.
-    let (addr, server) = warp::serve(routes).bind_with_graceful_shutdown(config.bind_addr, async {
-        signal::ctrl_c()
-            .await
-            .expect("Failed to install CTRL+C signal handler.");
-    });
-
-    // Run server to completion.
-    info!("Starting server on {} ...", addr);
-    if let Err(e) = task::spawn(server).await {
-        error!("Server died unexpectedly: {}", e.to_string());
+    // Create server with shutdown signal. Runs in progress are cancelled so
+    // that they log off after their current command instead of holding up
+    // shutdown.
+    let cancel = config.cancel.clone();
+    let paths = match config.tls.clone() {
+        Some(paths) => paths,
+        None => {
+            let (addr, server) = warp::serve(routes)
+                .try_bind_with_graceful_shutdown(config.bind_addr, async move {
+                    signal::ctrl_c()
+                        .await
+                        .expect("Failed to install CTRL+C signal handler.");
+                    cancel.cancel();
+                })
+                .with_context(|| format!("Failed to bind server to {}.", config.bind_addr))?;
+
+            // Run server to completion.
+            info!("Starting server on {} ...", addr);
+            if let Err(e) = task::spawn(server).await {
+                error!("Server died unexpectedly: {}", e);
+            }
+            info!("Stopping server...");
+            return Ok(());
+        }
+    };
+
+    // Serve over TLS. On SIGHUP, the TLS files are read again, and if they
+    // are valid, the server is restarted with them once the old one has
+    // finished the requests in progress and let go of its address.
+    let mut files = paths.read()?;
+    let mut hangups = Hangups::new()?;
+    loop {
+        let (stop, stopped) = oneshot::channel::<()>();
+        let (addr, server) = files
+            .apply(warp::serve(routes.clone()).tls())
+            .try_bind_with_graceful_shutdown(config.bind_addr, async move {
+                let _ = stopped.await;
+            })
+            .with_context(|| format!("Failed to bind server to {}.", config.bind_addr))?;
+        info!("Starting server on https://{} ...", addr);
+        let mut running = task::spawn(server);
+
+        let reload = loop {
+            tokio::select! {
+                _ = signal::ctrl_c() => break None,
+                _ = hangups.recv() => match paths.read() {
+                    Ok(files) => break Some(files),
+                    Err(e) => error!("Failed to reload TLS files, keeping the old ones: {:#}", e),
+                },
+                result = &mut running => {
+                    if let Err(e) = result {
+                        error!("Server died unexpectedly: {}", e);
+                    }
+                    return Ok(());
+                }
+            }
+        };
+
+        if reload.is_none() {
+            cancel.cancel();
+        }
+        let _ = stop.send(());
+        if let Err(e) = running.await {
+            error!("Server died unexpectedly: {}", e);
+        }
+        match reload {
+            Some(reloaded) => {
+                info!("Reloading TLS files...");
+                files = reloaded;
+            }
+            None => break,
+        }
     }
     info!("Stopping server...");
 
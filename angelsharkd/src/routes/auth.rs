@@ -0,0 +1,230 @@
+use super::{dtos::Error, policy::Policy};
+use crate::{audit::AuditLog, config::Config};
+use anyhow::{anyhow, Context, Result};
+use libangelshark::{AcmRunner, Message, RunOutput};
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    sync::Arc,
+};
+use warp::{
+    addr, header,
+    hyper::{header::WWW_AUTHENTICATE, StatusCode},
+    reject::{self, Reject},
+    reply, Filter, Rejection, Reply,
+};
+
+/// API keys allowed to use the daemon, stored as SHA-256 hashes so that the
+/// keys file doesn't hold usable credentials.
+#[derive(Debug, Clone, Default)]
+pub struct ApiKeys(Arc<Vec<(String, String)>>);
+
+impl ApiKeys {
+    /// Reads a keys file. Every line that isn't blank or a `#` comment is a
+    /// key name and the lowercase hex SHA-256 hash of the key, separated by
+    /// whitespace.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read API keys file: {}", path.display()))?;
+
+        let mut keys = Vec::new();
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, hash) = line
+                .split_once(char::is_whitespace)
+                .map(|(name, hash)| (name, hash.trim().to_lowercase()))
+                .filter(|(_, hash)| hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit()))
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Invalid API key at line {}. Expected a name and a SHA-256 hash.",
+                        index + 1
+                    )
+                })?;
+            if keys.iter().any(|(other, _)| other == name) {
+                return Err(anyhow!(
+                    "Duplicate API key name {} at line {}.",
+                    name,
+                    index + 1
+                ));
+            }
+            keys.push((name.to_string(), hash));
+        }
+        Ok(Self(Arc::new(keys)))
+    }
+
+    /// Whether there is a key named `name`.
+    pub fn contains(&self, name: &str) -> bool {
+        self.0.iter().any(|(known, _)| known == name)
+    }
+
+    /// Returns the name of the key `token` hashes to, if any.
+    fn find(&self, token: &str) -> Option<&str> {
+        let hash: String = Sha256::digest(token.as_bytes())
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        self.0
+            .iter()
+            .find(|(_, known)| constant_time_eq(known.as_bytes(), hash.as_bytes()))
+            .map(|(name, _)| name.as_str())
+    }
+}
+
+/// Who sent a request, what they may do, and where what they do is audited.
+#[derive(Clone)]
+pub struct Caller {
+    /// The name of the caller's API key. `None` if API keys are turned off.
+    pub key: Option<String>,
+    /// The caller's IP address, if known.
+    pub addr: Option<IpAddr>,
+    policy: Option<Policy>,
+    audit: Option<AuditLog>,
+}
+
+impl Caller {
+    /// Whether the caller may run `command` on the ACM registered as `acm`.
+    /// Everything is allowed if there is no policy.
+    pub fn may_run(&self, runner: &AcmRunner, acm: &str, command: &str) -> bool {
+        match (&self.policy, &self.key) {
+            (None, _) => true,
+            (Some(policy), Some(key)) => policy.permits_command(key, runner, acm, command),
+            (Some(_), None) => false,
+        }
+    }
+
+    /// Whether the caller may use the extension route at `route`.
+    /// Everything is allowed if there is no policy.
+    pub fn may_use(&self, route: &str) -> bool {
+        match (&self.policy, &self.key) {
+            (None, _) => true,
+            (Some(policy), Some(key)) => policy.permits_extension(key, route),
+            (Some(_), None) => false,
+        }
+    }
+
+    /// Records the outputs of inputs the caller ran through `route` in the
+    /// audit log, if there is one. See [AuditLog::record_outputs].
+    pub fn audit_outputs(&self, route: &str, inputs: &[(String, Message)], outputs: &[RunOutput]) {
+        if let Some(audit) = &self.audit {
+            audit.record_outputs(self, route, inputs, outputs);
+        }
+    }
+
+    /// Records the outputs of inputs the caller ran on `acm` through `route`
+    /// in the audit log, if there is one. See [AuditLog::record_job].
+    pub fn audit_job(
+        &self,
+        route: &str,
+        acm: &str,
+        inputs: &[(String, Message)],
+        outputs: &[Message],
+        error: Option<&str>,
+    ) {
+        if let Some(audit) = &self.audit {
+            audit.record_job(self, route, acm, inputs, outputs, error);
+        }
+    }
+
+    /// Records an input the caller tried to run on `acm` through `route` in
+    /// the audit log, if there is one. See [AuditLog::record].
+    pub fn audit(&self, route: &str, acm: &str, input: &Message, error: Option<&str>) {
+        if let Some(audit) = &self.audit {
+            audit.record(self, route, acm, input, error);
+        }
+    }
+}
+
+/// Why a request was not authenticated or authorized.
+#[derive(Debug)]
+pub enum AuthError {
+    /// No `Authorization: Bearer` header was sent.
+    Missing,
+    /// The bearer token is not a known API key.
+    Invalid,
+    /// The caller's roles don't allow the request, for the given reason.
+    Forbidden(String),
+}
+
+impl Reject for AuthError {}
+
+/// Rejects requests without a known API key in an `Authorization: Bearer`
+/// header, and extracts the [Caller] of the rest. With no keys configured
+/// (only allowed in debug mode), every request is let through.
+pub fn authenticate(
+    config: &Config,
+) -> impl Filter<Extract = (Caller,), Error = Rejection> + Clone {
+    let (keys, policy, audit) = (
+        config.api_keys.clone(),
+        config.policy.clone(),
+        config.audit.clone(),
+    );
+    header::optional::<String>("authorization")
+        .and(addr::remote())
+        .and_then(
+            move |authorization: Option<String>, remote: Option<SocketAddr>| {
+                let (keys, policy, audit) = (keys.clone(), policy.clone(), audit.clone());
+                async move {
+                    let caller = |key: Option<&str>| Caller {
+                        key: key.map(String::from),
+                        addr: remote.map(|remote| remote.ip()),
+                        policy,
+                        audit,
+                    };
+                    let keys = match keys {
+                        Some(keys) => keys,
+                        None => return Ok(caller(None)),
+                    };
+                    let token = authorization
+                        .as_deref()
+                        .and_then(|value| value.strip_prefix("Bearer "))
+                        .ok_or_else(|| reject::custom(AuthError::Missing))?;
+                    match keys.find(token.trim()) {
+                        Some(key) => Ok(caller(Some(key))),
+                        None => Err(reject::custom(AuthError::Invalid)),
+                    }
+                }
+            },
+        )
+}
+
+/// Turns [AuthError] rejections into `401 Unauthorized` or `403 Forbidden`
+/// JSON errors, and passes every other rejection on.
+pub async fn handle_rejection(rejection: Rejection) -> Result<reply::Response, Rejection> {
+    let reason = match rejection.find::<AuthError>() {
+        Some(AuthError::Missing) => "Missing API key. Send it as 'Authorization: Bearer <key>'.",
+        Some(AuthError::Invalid) => "Invalid API key.",
+        Some(AuthError::Forbidden(reason)) => {
+            return Ok(reply::with_status(
+                reply::json(&Error {
+                    reason: reason.clone(),
+                }),
+                StatusCode::FORBIDDEN,
+            )
+            .into_response())
+        }
+        None => return Err(rejection),
+    };
+
+    Ok(reply::with_header(
+        reply::with_status(
+            reply::json(&Error {
+                reason: reason.into(),
+            }),
+            StatusCode::UNAUTHORIZED,
+        ),
+        WWW_AUTHENTICATE,
+        "Bearer",
+    )
+    .into_response())
+}
+
+/// Compares `a` and `b` in time that depends only on their lengths.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
@@ -1,4 +1,4 @@
-use libangelshark::Message;
+use libangelshark::{Message, OssiError, Plan, UnknownAcm};
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Debug)]
@@ -6,15 +6,34 @@ pub struct Error {
     pub reason: String,
 }
 
+/// The response to OSSI requests that the caller's roles don't allow.
+#[derive(Serialize, Debug)]
+pub struct Forbidden {
+    pub reason: String,
+    pub denied: Vec<Denied>,
+}
+
+/// One ACM a request was not allowed to run its command on. `request` is the
+/// index of the request in the body.
+#[derive(Serialize, Debug)]
+pub struct Denied {
+    pub request: usize,
+    pub acm: String,
+    pub command: String,
+}
+
 #[derive(Serialize, Debug)]
 pub struct Version {
     pub daemon_version: &'static str,
+    pub read_only: bool,
 }
 
 #[derive(Deserialize, Debug)]
 pub struct Query {
     pub no_cache: Option<bool>,
     pub panicky: Option<bool>,
+    pub stream: Option<bool>,
+    pub dry_run: Option<bool>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -25,22 +44,15 @@ pub struct Request {
     pub datas: Option<Vec<String>>,
 }
 
-impl From<Request> for Vec<(String, Message)> {
-    fn from(val: Request) -> Self {
-        val.acms
-            .iter()
-            .map(|name| {
-                (
-                    name.to_owned(),
-                    Message {
-                        command: val.command.clone(),
-                        fields: val.fields.clone(),
-                        datas: val.datas.clone().map(|d| vec![d]),
-                        error: None,
-                    },
-                )
-            })
-            .collect()
+impl Request {
+    /// The input to run on each of the request's ACMs.
+    pub fn message(&self) -> Message {
+        Message {
+            command: self.command.clone(),
+            fields: self.fields.clone(),
+            datas: self.datas.clone().map(|d| vec![d]),
+            error: None,
+        }
     }
 }
 
@@ -49,6 +61,8 @@ pub struct Response {
     pub acm: String,
     pub command: String,
     pub error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ossi_error: Option<OssiErrorDetail>,
     pub fields: Vec<String>,
     pub datas: Vec<Vec<String>>,
 }
@@ -58,6 +72,7 @@ impl From<(String, Message)> for Response {
         let (acm, msg) = msg;
         Self {
             acm,
+            ossi_error: msg.ossi_error().map(OssiErrorDetail::from),
             command: msg.command,
             fields: msg.fields.unwrap_or_default(),
             datas: msg.datas.unwrap_or_default(),
@@ -65,3 +80,116 @@ impl From<(String, Message)> for Response {
         }
     }
 }
+
+impl Response {
+    /// Error entries for each input of an unknown ACM, which were not run.
+    pub fn unknown_acm(unknown: UnknownAcm) -> Vec<Self> {
+        let error = unknown.to_string();
+        unknown
+            .inputs
+            .into_iter()
+            .map(|input| Self {
+                acm: unknown.job_name.clone(),
+                command: input.command,
+                error: error.clone(),
+                ossi_error: None,
+                fields: input.fields.unwrap_or_default(),
+                datas: Vec::new(),
+            })
+            .collect()
+    }
+}
+
+/// A structured breakdown of [Response::error], when it could be parsed.
+#[derive(Debug, Serialize)]
+pub struct OssiErrorDetail {
+    pub index: usize,
+    pub field: String,
+    pub code: String,
+    pub kind: &'static str,
+    pub text: String,
+}
+
+impl From<OssiError> for OssiErrorDetail {
+    fn from(error: OssiError) -> Self {
+        Self {
+            kind: error.kind().as_str(),
+            code: format!("{:04x}", error.code),
+            index: error.index,
+            field: error.field,
+            text: error.text,
+        }
+    }
+}
+
+/// One line of a streamed (`?stream=true`) OSSI response. Each line carries
+/// exactly one of `error`, `fields`, or `data`.
+#[derive(Debug, Serialize)]
+pub struct Row {
+    pub acm: String,
+    pub command: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fields: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Vec<String>>,
+}
+
+/// The response to a dry run (`?dry_run=true`): what would run, without
+/// running it.
+#[derive(Debug, Serialize)]
+pub struct PlanResponse {
+    pub valid: bool,
+    pub changes: usize,
+    pub inputs: Vec<PlanEntry>,
+}
+
+/// One input of a [PlanResponse].
+#[derive(Debug, Serialize)]
+pub struct PlanEntry {
+    pub acm: String,
+    pub command: String,
+    pub fields: Vec<String>,
+    pub known_acm: bool,
+    pub change: bool,
+    pub invalid_fields: Vec<String>,
+}
+
+impl From<Plan> for PlanResponse {
+    fn from(plan: Plan) -> Self {
+        let (valid, changes) = (plan.is_valid(), plan.changes());
+        let planned = plan.jobs.into_iter().flat_map(|job| {
+            let acm = job.job_name;
+            job.inputs.into_iter().map(move |i| PlanEntry {
+                acm: acm.clone(),
+                command: i.input.command,
+                fields: i.input.fields.unwrap_or_default(),
+                known_acm: true,
+                change: i.is_change,
+                invalid_fields: i.invalid_fields,
+            })
+        });
+        let unknown = plan.unknown.into_iter().flat_map(|unknown| {
+            let acm = unknown.job_name;
+            unknown.inputs.into_iter().map(move |input| PlanEntry {
+                acm: acm.clone(),
+                change: input.is_change(),
+                invalid_fields: input
+                    .invalid_fields()
+                    .into_iter()
+                    .map(String::from)
+                    .collect(),
+                command: input.command,
+                fields: input.fields.unwrap_or_default(),
+                known_acm: false,
+            })
+        });
+
+        Self {
+            valid,
+            changes,
+            inputs: planned.chain(unknown).collect(),
+        }
+    }
+}
@@ -1,5 +1,9 @@
+use super::auth::{self, AuthError, Caller};
 use crate::config::Config;
-use warp::{path, Filter, Rejection, Reply};
+use warp::{path, reject, Filter, Rejection, Reply};
+
+/// The first path segments of extension routes that change ACMs.
+const CHANGE_ROUTES: [&str; 2] = ["deprov", "service"];
 
 #[cfg(feature = "simple_busy")]
 mod simple_busy;
@@ -23,18 +27,59 @@ pub fn filter(_config: &Config) -> impl Filter<Extract = impl Reply, Error = Rej
     #[cfg(feature = "simple_search")]
     let filters = filters
         .or(simple_search::search_filter(haystack.clone()))
-        .or(simple_search::refresh_filter(haystack));
+        .or(simple_search::refresh_filter(haystack, _config));
 
     #[cfg(feature = "simple_deprov")]
-    let filters = filters.or(simple_deprov::filter(_config.runner.clone()));
+    let filters = filters.or(simple_deprov::filter(_config));
 
     #[cfg(feature = "simple_busy")]
     let filters = filters
-        .or(simple_busy::busy_filter(_config.runner.to_owned()))
-        .or(simple_busy::release_filter(_config.runner.to_owned()))
-        .or(simple_busy::toggle_filter(_config.runner.to_owned()));
+        .or(simple_busy::busy_filter(_config))
+        .or(simple_busy::release_filter(_config))
+        .or(simple_busy::toggle_filter(_config));
+
+    path("extensions")
+        .and(authorize(_config))
+        .and(writable(_config))
+        .and(filters)
+}
+
+/// Rejects requests for extension routes that change ACMs (`simple_deprov`
+/// and `simple_busy`) in read-only mode.
+fn writable(config: &Config) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    let read_only = config.read_only;
+    path::peek()
+        .and_then(move |peek: path::Peek| async move {
+            match peek.segments().next() {
+                Some(route) if read_only && CHANGE_ROUTES.contains(&route) => {
+                    Err(reject::custom(AuthError::Forbidden(format!(
+                        "Read-only mode. /extensions/{} is disabled.",
+                        route
+                    ))))
+                }
+                _ => Ok(()),
+            }
+        })
+        .untuple_one()
+}
 
-    path("extensions").and(filters)
+/// Rejects callers whose roles don't allow the extension route they asked
+/// for. The extension index is open to every caller.
+fn authorize(config: &Config) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    auth::authenticate(config)
+        .and(path::peek())
+        .and_then(|caller: Caller, peek: path::Peek| async move {
+            let route = peek.as_str().trim_matches('/');
+            if route.is_empty() || caller.may_use(route) {
+                Ok(())
+            } else {
+                Err(reject::custom(AuthError::Forbidden(format!(
+                    "Not allowed to use /extensions/{}.",
+                    route
+                ))))
+            }
+        })
+        .untuple_one()
 }
 
 /// The default, informational extension route.
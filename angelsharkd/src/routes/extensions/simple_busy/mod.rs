@@ -1,7 +1,15 @@
-use crate::routes::dtos::{Error, Response};
-use libangelshark::{AcmRunner, Message, ParallelIterator};
+use crate::{
+    config::Config,
+    routes::{
+        auth::{self, Caller},
+        dtos::{Error, Response},
+        queue_inputs,
+    },
+};
+use libangelshark::{AcmRunner, Message};
 use log::error;
 use serde::Deserialize;
+use std::convert::Infallible;
 use warp::{
     body::{content_length_limit, json},
     hyper::StatusCode,
@@ -11,59 +19,88 @@ use warp::{
 const SIXTEEN_K: u64 = 1024 * 16;
 
 pub fn busy_filter(
-    runner: AcmRunner,
+    config: &Config,
 ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    let runner = config.runner.clone();
     post()
         .and(path!("service" / "busyout" / ..))
+        .and(auth::authenticate(config))
         .and(content_length_limit(SIXTEEN_K))
         .and(json())
-        .map(move |entries| queue_and_run(entries, "busyout", runner.to_owned()))
+        .and_then(move |caller, entries| {
+            queue_and_run(caller, entries, "busyout", runner.to_owned())
+        })
 }
 
 pub fn release_filter(
-    runner: AcmRunner,
+    config: &Config,
 ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    let runner = config.runner.clone();
     post()
         .and(path!("service" / "release" / ..))
+        .and(auth::authenticate(config))
         .and(content_length_limit(SIXTEEN_K))
         .and(json())
-        .map(move |entries| queue_and_run(entries, "release", runner.to_owned()))
+        .and_then(move |caller, entries| {
+            queue_and_run(caller, entries, "release", runner.to_owned())
+        })
 }
 
 pub fn toggle_filter(
-    runner: AcmRunner,
+    config: &Config,
 ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    let runner = config.runner.clone();
     post()
         .and(path!("service" / "toggle" / ..))
+        .and(auth::authenticate(config))
         .and(content_length_limit(SIXTEEN_K))
         .and(json())
-        .map(move |entries| queue_and_run(entries, "toggle", runner.to_owned()))
+        .and_then(move |caller, entries| {
+            queue_and_run(caller, entries, "toggle", runner.to_owned())
+        })
 }
 
-fn queue_and_run(entries: Entries, command: &str, mut runner: AcmRunner) -> impl Reply {
+async fn queue_and_run(
+    caller: Caller,
+    entries: Entries,
+    command: &str,
+    mut runner: AcmRunner,
+) -> Result<impl Reply, Infallible> {
+    let mut inputs = Vec::new();
     for entry in entries.into_iter() {
         if command == "toggle" {
-            runner.queue_input(
-                &entry.acm,
-                &Message::new(&format!("busyout station {}", entry.ext)),
-            );
-            runner.queue_input(
-                &entry.acm,
-                &Message::new(&format!("release station {}", entry.ext)),
-            );
+            inputs.push((
+                entry.acm.clone(),
+                Message::new(&format!("busyout station {}", entry.ext)),
+            ));
+            inputs.push((
+                entry.acm,
+                Message::new(&format!("release station {}", entry.ext)),
+            ));
         } else {
-            runner.queue_input(
-                &entry.acm,
-                &Message::new(&format!("{} station {}", command, entry.ext)),
-            );
+            inputs.push((
+                entry.acm,
+                Message::new(&format!("{} station {}", command, entry.ext)),
+            ));
         }
     }
+    let inputs = queue_inputs(&mut runner, inputs);
 
     // generate output on runner
-    let output: Result<Vec<Vec<_>>, _> = runner
-        .run()
+    let outputs = runner.run_async().await;
+    caller.audit_outputs(
+        &format!("/extensions/service/{}", command),
+        &inputs,
+        &outputs,
+    );
+    let output: Result<Vec<Vec<_>>, _> = outputs
+        .into_iter()
         .map(|(name, output)| -> Result<Vec<Response>, anyhow::Error> {
-            Ok(output?
+            let output = match output {
+                Ok(output) => output,
+                Err(e) => return e.downcast().map(Response::unknown_acm),
+            };
+            Ok(output
                 .into_iter()
                 .filter_map(move |msg| {
                     (msg.command != "logoff").then(|| Response::from((name.to_owned(), msg)))
@@ -73,7 +110,7 @@ fn queue_and_run(entries: Entries, command: &str, mut runner: AcmRunner) -> impl
         .collect();
 
     // handle errors and package output as json
-    match output {
+    Ok(match output {
         Err(e) => {
             error!("busyout-release extension: {}", e);
             reply::with_status(
@@ -87,7 +124,7 @@ fn queue_and_run(entries: Entries, command: &str, mut runner: AcmRunner) -> impl
             reply::json(&r.into_iter().flatten().collect::<Vec<_>>()),
             StatusCode::OK,
         ),
-    }
+    })
 }
 
 type Entries = Vec<Entry>;
@@ -1,4 +1,11 @@
-use libangelshark::{AcmRunner, Message, ParallelIterator};
+use crate::{
+    config::Config,
+    routes::{
+        auth::{self, Caller},
+        queue_inputs,
+    },
+};
+use libangelshark::{AcmRunner, Message};
 use log::{error, info};
 use serde::Deserialize;
 use std::convert::Infallible;
@@ -10,44 +17,54 @@ use warp::{
 const SIXTEEN_K: u64 = 1024 * 16;
 
 /// Returns a warp filter to handle HTTP POSTs for deprovisioning stations, agents, etc.
-pub fn filter(runner: AcmRunner) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+pub fn filter(config: &Config) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    let runner = config.runner.clone();
     warp::path("deprov")
         .and(post())
+        .and(auth::authenticate(config))
         .and(content_length_limit(SIXTEEN_K))
         .and(json())
-        .and_then(move |entries| remove_entries(entries, runner.to_owned()))
+        .and_then(move |caller, entries| remove_entries(caller, entries, runner.to_owned()))
 }
 
 /// Queues removal commands for [Entries] on an [AcmRunner]. Gathers any errors encountered and returns those.
-async fn remove_entries(entries: Entries, mut runner: AcmRunner) -> Result<impl Reply, Infallible> {
+async fn remove_entries(
+    caller: Caller,
+    entries: Entries,
+    mut runner: AcmRunner,
+) -> Result<impl Reply, Infallible> {
     // Construct OSSI messages to carry out removals.
+    let mut inputs = Vec::new();
     for entry in entries {
         match entry {
             Entry::StationUser { acm, ext } => {
-                runner.queue_input(&acm, &Message::new(&format!("list station {}", ext)));
-                runner.queue_input(&acm, &Message::new(&format!("clear amw all {}", ext)));
-                runner.queue_input(&acm, &Message::new(&format!("remove station {}", ext)));
+                inputs.push((acm.clone(), Message::new(&format!("list station {}", ext))));
+                inputs.push((acm.clone(), Message::new(&format!("clear amw all {}", ext))));
+                inputs.push((acm, Message::new(&format!("remove station {}", ext))));
             }
             Entry::AgentLoginId { acm, ext } => {
-                runner.queue_input(&acm, &Message::new(&format!("list agent-loginID {}", ext)));
-                runner.queue_input(
-                    &acm,
-                    &Message::new(&format!("remove agent-loginID {}", ext)),
-                );
+                inputs.push((
+                    acm.clone(),
+                    Message::new(&format!("list agent-loginID {}", ext)),
+                ));
+                inputs.push((acm, Message::new(&format!("remove agent-loginID {}", ext))));
             }
         }
     }
+    let inputs = queue_inputs(&mut runner, inputs);
 
     // Gather any errors encountered and format them for the client response.
-    let errors: Vec<String> = runner
-        .run_cached()
-        .map(|(acm, output)| match output {
+    let outputs = runner.run_cached_async().await;
+    caller.audit_outputs("/extensions/deprov", &inputs, &outputs);
+    let errors: Vec<String> = outputs
+        .into_iter()
+        .flat_map(|(acm, output)| match output {
             Ok(messages) => messages
                 .into_iter()
                 .filter_map(|message| {
                     if let Some(data) = message
                         .datas
-                        .and_then(|d| Some(format!("{acm}\t{}", d.get(0)?.join("\t"))))
+                        .and_then(|d| Some(format!("{acm}\t{}", d.first()?.join("\t"))))
                     {
                         // if there was data (such as in lists), print it out
                         info!("{data}");
@@ -57,7 +74,6 @@ async fn remove_entries(entries: Entries, mut runner: AcmRunner) -> Result<impl
                 .collect(),
             Err(error) => vec![format!("ACM {}: {}", acm, error)],
         })
-        .flatten()
         .collect();
 
     // Log errors for tracking.
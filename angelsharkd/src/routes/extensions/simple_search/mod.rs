@@ -1,3 +1,7 @@
+use crate::{
+    config::Config,
+    routes::auth::{self, Caller},
+};
 use log::{error, info};
 use std::{convert::Infallible, thread};
 pub use types::Haystack;
@@ -31,10 +35,12 @@ pub fn search_filter(
 /// Returns a warp filter to handle HTTP GETs for refreshing the haystack.
 pub fn refresh_filter(
     haystack: Haystack,
+    config: &Config,
 ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
     path!("search" / "refresh")
         .and(get())
-        .and_then(move || refresh(haystack.to_owned()))
+        .and(auth::authenticate(config))
+        .and_then(move |caller| refresh(haystack.to_owned(), caller))
 }
 
 /// Runs the search request to find all needles in the haystack and converts the
@@ -60,7 +66,7 @@ async fn search(
 
 /// Immediately returns. Spawns an asynchronous task to complete the haystack
 /// refresh in the background.
-async fn refresh(haystack: Haystack) -> Result<impl Reply, Infallible> {
+async fn refresh(haystack: Haystack, caller: Caller) -> Result<impl Reply, Infallible> {
     // Note: why use `thread::spawn()`? Previously I had used `tokio::spawn()`
     // here. The difference is that `tokio::spawn()` spawns the task in a thread
     // from Tokio's dedicated threadpool. This means that this thread is no
@@ -74,8 +80,8 @@ async fn refresh(haystack: Haystack) -> Result<impl Reply, Infallible> {
 
     // Run refresh as a background task and immediately return.
     thread::spawn(move || {
-        if let Err(e) = haystack.refresh() {
-            error!("{}", e.to_string());
+        if let Err(e) = haystack.refresh(&caller) {
+            error!("{}", e);
         } else {
             info!("Search haystack refreshed.");
         }
@@ -1,5 +1,6 @@
+use crate::routes::{auth::Caller, queue_inputs};
 use anyhow::{anyhow, Context, Error};
-use libangelshark::{AcmRunner, Message, ParallelIterator};
+use libangelshark::{AcmRunner, Message, ParallelIterator, RunOutput};
 use log::{error, info};
 use serde::Deserialize;
 use std::{
@@ -76,10 +77,12 @@ impl Haystack {
 
     /// Refreshes the haystack data by running relevant commands on a runner,
     /// parsing the results, and updating the entries field with the fresh data.
-    /// Note: multiple simultaenous refresh calls could starve the Rayon thread
-    /// pool used by `libangelshark`. The entry generation could probably be
-    /// simplified and the number of clones reduced.
-    pub fn refresh(&self) -> Result<(), Error> {
+    /// The commands are audited as run by `caller`.
+    /// The runner's executor limits how many ACM runs happen at once, so
+    /// simultaneous refresh calls queue behind each other and other requests.
+    /// The entry generation could probably be simplified and the number of
+    /// clones reduced.
+    pub fn refresh(&self, caller: &Caller) -> Result<(), Error> {
         let mut runner = self.runner.to_owned();
 
         // Queue jobs in ACM runner
@@ -91,26 +94,32 @@ impl Haystack {
         })?;
 
         // Generate jobs and queue on runner.
-        for acm in configured_acms.split_whitespace() {
-            runner
-                .queue_input(acm, &Message::new(OSSI_LIST_EXT_CMD))
-                .queue_input(
-                    acm,
-                    &Message {
-                        command: String::from(OSSI_LIST_STAT_CMD),
-                        fields: Some(vec![
-                            String::from(OSSI_STAT_NUMBER_FIELD),
-                            String::from(OSSI_STAT_ROOM_FIELD),
-                        ]),
-                        datas: None,
-                        error: None,
-                    },
-                );
-        }
+        let inputs = queue_inputs(
+            &mut runner,
+            configured_acms.split_whitespace().flat_map(|acm| {
+                [
+                    (acm.to_owned(), Message::new(OSSI_LIST_EXT_CMD)),
+                    (
+                        acm.to_owned(),
+                        Message {
+                            command: String::from(OSSI_LIST_STAT_CMD),
+                            fields: Some(vec![
+                                String::from(OSSI_STAT_NUMBER_FIELD),
+                                String::from(OSSI_STAT_ROOM_FIELD),
+                            ]),
+                            datas: None,
+                            error: None,
+                        },
+                    ),
+                ]
+            }),
+        );
 
         // Run jobs and collect output. Filter out uneeded commands, combine errors.
-        let output: Result<Vec<(String, Vec<Message>)>, Error> = runner
-            .run()
+        let outputs: Vec<RunOutput> = runner.run().collect();
+        caller.audit_outputs("/extensions/search/refresh", &inputs, &outputs);
+        let output: Result<Vec<(String, Vec<Message>)>, Error> = outputs
+            .into_iter()
             .map(|(name, output)| {
                 let output: Vec<Message> = output?
                     .into_iter()
@@ -139,7 +148,7 @@ impl Haystack {
                     .filter(|message| message.command == OSSI_LIST_STAT_CMD)
                     .filter_map(|message| message.datas.to_owned())
                     .flatten()
-                    .filter_map(|stat| Some((stat.get(0)?.to_owned(), stat.get(1)?.to_owned())))
+                    .filter_map(|stat| Some((stat.first()?.to_owned(), stat.get(1)?.to_owned())))
             })
             .collect();
 
@@ -157,7 +166,7 @@ impl Haystack {
                     .flatten()
                     .map(move |mut extension| {
                         let room = extension
-                            .get(0)
+                            .first()
                             .and_then(|num| rooms.get(num))
                             .map(|room| room.to_owned())
                             .unwrap_or_else(String::new);
@@ -1,33 +1,43 @@
 use crate::config::Config;
 use anyhow::Error as AnyhowError;
+use auth::Caller;
 use dtos::*;
-use libangelshark::{AcmRunner, Message, ParallelIterator};
-use log::debug;
-use std::convert::Infallible;
+use libangelshark::{AcmRunner, Message, OutputRow, ParallelIterator, UnknownAcm};
+use log::{debug, info, warn};
+use std::{convert::Infallible, thread};
+use tokio::{sync::mpsc, task};
 use warp::{
     body,
-    hyper::{header, StatusCode},
+    hyper::{header, Body, StatusCode},
     path, post,
     reply::{self, with},
     Filter, Rejection, Reply,
 };
 
+pub mod auth;
 mod dtos;
 pub mod extensions;
+pub mod policy;
+
+/// The route of OSSI requests, as it is audited.
+const OSSI_ROUTE: &str = "/ossi";
 
 /// GET / -> Name and version # of app.
-pub fn index() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
-    path::end().and_then(handle_version)
+pub fn index(config: &Config) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    let read_only = config.read_only;
+    path::end().and_then(move || handle_version(read_only))
 }
 
 /// POST /ossi with JSON inputs -> JSON outputs
 pub fn ossi(config: &Config) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
-    let runner = config.runner.clone();
+    let (runner, read_only) = (config.runner.clone(), config.read_only);
     path("ossi")
         .and(post())
+        .and(auth::authenticate(config))
         .and(warp::query::<Query>())
         .and(json_body())
         .and(with_runner(runner))
+        .and(warp::any().map(move || read_only))
         .and_then(handle_ossi)
         .with(with::header(header::PRAGMA, "no-cache"))
         .with(with::header(header::CACHE_CONTROL, "no-store, max-age=0"))
@@ -47,67 +57,119 @@ fn json_body() -> impl Filter<Extract = (Vec<Request>,), Error = Rejection> + Cl
 }
 
 /// Handle version requests.
-async fn handle_version() -> Result<impl Reply, Infallible> {
+async fn handle_version(read_only: bool) -> Result<impl Reply, Infallible> {
     Ok(reply::json(&Version {
         daemon_version: env!("CARGO_PKG_VERSION"),
+        read_only,
     }))
 }
 
 /// Handle OSSI requests.
 async fn handle_ossi(
+    caller: Caller,
     query: Query,
     requests: Vec<Request>,
     mut runner: AcmRunner,
-) -> Result<impl Reply, Infallible> {
+    read_only: bool,
+) -> Result<reply::Response, Infallible> {
     debug!("{:?}", query);
     debug!("{:?}", requests);
 
-    // Queue request inputs on runner.
-    for (job_name, input) in requests
-        .into_iter()
-        .flat_map(|r| -> Vec<(String, Message)> { r.into() })
-    {
-        runner.queue_input(&job_name, &input);
+    // Refuse to run anything if read-only mode or the caller's roles don't
+    // allow all of it.
+    let mut reason =
+        "Read-only mode. Only list, display, and status commands may run. Nothing was run.";
+    let mut denied = denials(&requests, &runner, |_, command| {
+        !read_only || Message::new(command).is_read_only()
+    });
+    if denied.is_empty() {
+        reason = "Not allowed to run every request. Nothing was run.";
+        denied = denials(&requests, &runner, |acm, command| {
+            caller.may_run(&runner, acm, command)
+        });
+    }
+    if !denied.is_empty() {
+        warn!(
+            "Key {} denied {} input(s): {}",
+            caller.key.as_deref().unwrap_or("(none)"),
+            denied.len(),
+            reason
+        );
+        for denial in &denied {
+            let input = requests[denial.request].message();
+            caller.audit(OSSI_ROUTE, &denial.acm, &input, Some(reason));
+        }
+        return Ok(reply::with_status(
+            reply::json(&Forbidden {
+                reason: reason.into(),
+                denied,
+            }),
+            StatusCode::FORBIDDEN,
+        )
+        .into_response());
+    }
+
+    // Queue request inputs on runner, keeping them to audit.
+    let inputs = queue_inputs(
+        &mut runner,
+        requests.iter().flat_map(|request| {
+            let input = request.message();
+            request
+                .acms
+                .iter()
+                .map(move |label| (label.clone(), input.clone()))
+        }),
+    );
+
+    // Describe what would run without running it.
+    if query.dry_run.unwrap_or_default() {
+        return Ok(reply::json(&PlanResponse::from(runner.plan())).into_response());
+    }
+
+    // Stream rows back as they arrive instead of collecting them.
+    if query.stream.unwrap_or_default() {
+        return Ok(stream_ossi(runner, caller, inputs));
     }
 
-    // Collect runner results and convert to responses.
-    let responses: Vec<Result<Vec<Response>, AnyhowError>> = if query.no_cache.unwrap_or_default() {
-        // Run without cache.
+    // Run on Tokio's blocking threads and convert to responses, keeping request order.
+    let outputs = if query.no_cache.unwrap_or_default() {
         runner
-            .run()
-            .map(|(name, output)| {
-                let output: Vec<Response> = output?
-                    .into_iter()
-                    .filter_map(move |o| {
-                        if o.command == "logoff" {
-                            None
-                        } else {
-                            Some(Response::from((name.clone(), o)))
-                        }
-                    })
-                    .collect();
-                Ok(output)
+            .run_reports_async()
+            .await
+            .into_iter()
+            .map(|(name, report)| {
+                info!("ACM {}: {}", name, report);
+                for command in &report.commands {
+                    debug!("ACM {}: {}", name, command);
+                }
+                (name, report.into_outputs())
             })
             .collect()
     } else {
-        // Run with cache.
-        runner
-            .run_cached()
-            .map(|(name, output)| {
-                let output: Vec<Response> = output?
-                    .into_iter()
-                    .filter_map(move |o| {
-                        if o.command == "logoff" {
-                            None
-                        } else {
-                            Some(Response::from((name.clone(), o)))
-                        }
-                    })
-                    .collect();
-                Ok(output)
-            })
-            .collect()
+        runner.run_cached_async().await
     };
+    caller.audit_outputs(OSSI_ROUTE, &inputs, &outputs);
+    let responses: Vec<Result<Vec<Response>, AnyhowError>> = outputs
+        .into_iter()
+        .map(|(name, output)| {
+            // Unknown ACMs get an error entry for each of their inputs.
+            let output = match output {
+                Ok(output) => output,
+                Err(e) => return e.downcast().map(Response::unknown_acm),
+            };
+            let output: Vec<Response> = output
+                .into_iter()
+                .filter_map(move |o| {
+                    if o.command == "logoff" {
+                        None
+                    } else {
+                        Some(Response::from((name.clone(), o)))
+                    }
+                })
+                .collect();
+            Ok(output)
+        })
+        .collect();
 
     // Handle errors from runner.
     if query.panicky.unwrap_or_default() {
@@ -119,11 +181,13 @@ async fn handle_ossi(
                     reason: e.to_string(),
                 }),
                 StatusCode::INTERNAL_SERVER_ERROR,
-            )),
+            )
+            .into_response()),
             Ok(r) => Ok(reply::with_status(
                 reply::json(&r.into_iter().flatten().collect::<Vec<Response>>()),
                 StatusCode::OK,
-            )),
+            )
+            .into_response()),
         }
     } else {
         // Discard errors and return just good data.
@@ -132,6 +196,157 @@ async fn handle_ossi(
             .filter_map(|r| r.ok())
             .flatten()
             .collect();
-        Ok(reply::with_status(reply::json(&responses), StatusCode::OK))
+        Ok(reply::with_status(reply::json(&responses), StatusCode::OK).into_response())
     }
 }
+
+/// Queues each input on `runner` for every ACM its label expands to, and
+/// returns the inputs queued for each ACM, in order, to audit.
+pub fn queue_inputs(
+    runner: &mut AcmRunner,
+    inputs: impl IntoIterator<Item = (String, Message)>,
+) -> Vec<(String, Message)> {
+    let inputs: Vec<(String, Message)> = inputs
+        .into_iter()
+        .flat_map(|(label, input)| {
+            runner
+                .expand(&label)
+                .into_iter()
+                .map(move |acm| (acm, input.clone()))
+        })
+        .collect();
+    for (acm, input) in &inputs {
+        runner.queue_input(acm, input);
+    }
+    inputs
+}
+
+/// Lists every ACM, after expanding groups and patterns, that a request's
+/// command is not `allowed` on.
+fn denials(
+    requests: &[Request],
+    runner: &AcmRunner,
+    allowed: impl Fn(&str, &str) -> bool,
+) -> Vec<Denied> {
+    requests
+        .iter()
+        .enumerate()
+        .flat_map(|(index, request)| {
+            request
+                .acms
+                .iter()
+                .flat_map(|label| runner.expand(label))
+                .filter(|acm| !allowed(acm, &request.command))
+                .map(move |acm| Denied {
+                    request: index,
+                    acm,
+                    command: request.command.clone(),
+                })
+        })
+        .collect()
+}
+
+/// Runs queued OSSI requests and streams their output back as
+/// newline-delimited JSON [Row]s as soon as each row is read from an ACM.
+/// Streamed requests are never cached.
+fn stream_ossi(
+    runner: AcmRunner,
+    caller: Caller,
+    inputs: Vec<(String, Message)>,
+) -> reply::Response {
+    let (rows_tx, mut rows_rx) = mpsc::channel::<Row>(64);
+    let (mut body_tx, body) = Body::channel();
+
+    // Note: like the simple_search refresh, this runs on a plain thread so
+    // that long-running streams don't hold up Tokio's worker threads.
+    thread::spawn(move || {
+        runner.run_stream().for_each(|(acm, output)| {
+            let send = |command: &str, error, fields, data| {
+                rows_tx
+                    .blocking_send(Row {
+                        acm: acm.clone(),
+                        command: command.into(),
+                        error,
+                        fields,
+                        data,
+                    })
+                    .is_ok()
+            };
+
+            let rows = match output {
+                Ok(rows) => rows,
+                Err(e) => {
+                    caller.audit_job(OSSI_ROUTE, &acm, &inputs, &[], Some(&e.to_string()));
+                    match e.downcast_ref::<UnknownAcm>() {
+                        Some(unknown) => unknown.inputs.iter().for_each(|input| {
+                            send(&input.command, Some(e.to_string()), None, None);
+                        }),
+                        None => {
+                            send("", Some(e.to_string()), None, None);
+                        }
+                    }
+                    return;
+                }
+            };
+
+            // Keep each command's error, and whether its output was read to
+            // the end, to audit.
+            let (mut outputs, mut ended, mut error) = (Vec::<Message>::new(), true, None);
+            let mut command = String::new();
+            for row in rows {
+                // Stop reading if the client has gone away.
+                let sent = match row {
+                    Err(e) => {
+                        send(&command, Some(e.to_string()), None, None);
+                        error = Some(e.to_string());
+                        break;
+                    }
+                    Ok(OutputRow::Command(c)) => {
+                        outputs.push(Message::new(&c));
+                        ended = false;
+                        command = c;
+                        true
+                    }
+                    Ok(_) if command == "logoff" => true,
+                    Ok(OutputRow::Error(e)) => {
+                        if let Some(output) = outputs.last_mut() {
+                            output.error = Some(e.clone());
+                        }
+                        send(&command, Some(e), None, None)
+                    }
+                    Ok(OutputRow::Fields(f)) => send(&command, None, Some(f), None),
+                    Ok(OutputRow::Data(d)) => send(&command, None, None, Some(d)),
+                    Ok(OutputRow::End) => {
+                        ended = true;
+                        true
+                    }
+                };
+                if !sent {
+                    error = Some("The client closed the stream.".into());
+                    break;
+                }
+            }
+            if !ended {
+                outputs.pop();
+            }
+            caller.audit_job(OSSI_ROUTE, &acm, &inputs, &outputs, error.as_deref());
+        });
+    });
+
+    task::spawn(async move {
+        while let Some(row) = rows_rx.recv().await {
+            let mut line = serde_json::to_vec(&row).unwrap_or_default();
+            line.push(b'\n');
+            if body_tx.send_data(line.into()).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    reply::with_header(
+        reply::Response::new(body),
+        header::CONTENT_TYPE,
+        "application/x-ndjson",
+    )
+    .into_response()
+}
@@ -0,0 +1,159 @@
+use anyhow::{anyhow, Context, Result};
+use glob::Pattern;
+use libangelshark::AcmRunner;
+use serde::Deserialize;
+use std::{collections::BTreeMap, fs, path::Path, sync::Arc};
+
+/// Which API keys may run which OSSI commands on which ACMs, and use which
+/// extension routes, by role. Anything no role allows is denied. ACM names,
+/// commands, and routes are matched without regard to case.
+#[derive(Debug, Clone, Default)]
+pub struct Policy(Arc<Vec<Role>>);
+
+/// What the API keys given a role may do.
+#[derive(Debug)]
+struct Role {
+    name: String,
+    keys: Vec<String>,
+    acms: Vec<String>,
+    commands: Vec<Vec<Pattern>>,
+    extensions: Vec<Pattern>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PolicyFile {
+    #[serde(default)]
+    roles: BTreeMap<String, RoleFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RoleFile {
+    keys: Vec<String>,
+    #[serde(default)]
+    acms: Vec<String>,
+    #[serde(default)]
+    commands: Vec<String>,
+    #[serde(default)]
+    extensions: Vec<String>,
+}
+
+impl Policy {
+    /// Reads a TOML policy file with a `[roles.NAME]` table for each role.
+    /// See the README for its format.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read policy file: {}", path.display()))?;
+        let file: PolicyFile =
+            toml::from_str(&contents).with_context(|| "Failed to parse policy file.")?;
+
+        let roles = file
+            .roles
+            .into_iter()
+            .map(|(name, role)| {
+                let pattern = |rule: &str| {
+                    Pattern::new(&rule.to_lowercase())
+                        .with_context(|| format!("Invalid pattern {} in role {}.", rule, name))
+                };
+                let commands = role
+                    .commands
+                    .iter()
+                    .map(|rule| {
+                        let words: Vec<&str> = rule.split_whitespace().collect();
+                        if words.is_empty() || words.len() > 2 {
+                            return Err(anyhow!(
+                                "Invalid command {:?} in role {}. Expected a verb and an optional object.",
+                                rule,
+                                name
+                            ));
+                        }
+                        words.into_iter().map(pattern).collect()
+                    })
+                    .collect::<Result<_>>()?;
+                let extensions = role
+                    .extensions
+                    .iter()
+                    .map(|rule| pattern(rule.trim_matches('/')))
+                    .collect::<Result<_>>()?;
+                for acm in role.acms.iter().filter(|acm| !acm.starts_with('@')) {
+                    pattern(acm)?;
+                }
+
+                let acms = role
+                    .acms
+                    .into_iter()
+                    .map(|acm| match acm.starts_with('@') {
+                        true => acm,
+                        false => acm.to_lowercase(),
+                    })
+                    .collect();
+
+                Ok(Role {
+                    keys: role.keys,
+                    acms,
+                    commands,
+                    extensions,
+                    name,
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self(Arc::new(roles)))
+    }
+
+    /// Every API key name given a role, with the name of the role.
+    pub fn keys(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().flat_map(|role| {
+            role.keys
+                .iter()
+                .map(move |key| (key.as_str(), role.name.as_str()))
+        })
+    }
+
+    /// Whether `key` may run `command` on the ACM registered as `acm`. ACM
+    /// rules may be names, glob patterns, or groups registered on `runner`.
+    pub fn permits_command(&self, key: &str, runner: &AcmRunner, acm: &str, command: &str) -> bool {
+        let acm = acm.to_lowercase();
+        let command: Vec<String> = command.split_whitespace().map(str::to_lowercase).collect();
+        self.roles(key).any(|role| {
+            role.acms.iter().any(|rule| match rule.strip_prefix('@') {
+                Some(_) => runner
+                    .expand(rule)
+                    .iter()
+                    .any(|name| name.to_lowercase() == acm),
+                None => Pattern::new(rule).is_ok_and(|rule| rule.matches(&acm)),
+            }) && role.commands.iter().any(|rule| {
+                rule.len() <= command.len()
+                    && rule
+                        .iter()
+                        .zip(&command)
+                        .all(|(rule, word)| word_matches(rule, word))
+            })
+        })
+    }
+
+    /// Whether `key` may use the extension route at `route` (ex.
+    /// `service/busyout`).
+    pub fn permits_extension(&self, key: &str, route: &str) -> bool {
+        let route = route.trim_matches('/').to_lowercase();
+        self.roles(key)
+            .any(|role| role.extensions.iter().any(|rule| rule.matches(&route)))
+    }
+
+    fn roles<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a Role> {
+        self.0
+            .iter()
+            .filter(move |role| role.keys.iter().any(|k| k == key))
+    }
+}
+
+/// Whether a word of a command matches a word of a command rule. Like
+/// [libangelshark::Message::is_change], abbreviations of at least three
+/// letters (ex. `dis` for `display`) match rules without wildcards.
+fn word_matches(rule: &Pattern, word: &str) -> bool {
+    rule.matches(word)
+        || (word.len() >= 3
+            && !rule.as_str().contains(['*', '?', '['])
+            && rule.as_str().starts_with(word))
+}
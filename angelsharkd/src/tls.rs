@@ -0,0 +1,139 @@
+use anyhow::{bail, Context, Result};
+use rustls::{
+    pki_types::{CertificateDer, PrivateKeyDer},
+    server::WebPkiClientVerifier,
+    RootCertStore, ServerConfig,
+};
+use std::{env, fs, path::PathBuf, sync::Arc};
+use tokio::signal;
+use warp::{Filter, Rejection, Reply, TlsServer};
+
+/// Paths of the daemon's TLS certificate chain and private key, and of the CA
+/// certificates that clients must present a certificate signed by, if any.
+#[derive(Debug, Clone)]
+pub struct TlsPaths {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub client_ca: Option<PathBuf>,
+}
+
+/// The contents of [TlsPaths], read all at once so that a server never mixes
+/// old and new files.
+pub struct TlsFiles {
+    cert: Vec<u8>,
+    key: Vec<u8>,
+    client_ca: Option<Vec<u8>>,
+}
+
+impl TlsPaths {
+    /// Reads the paths from `ANGELSHARKD_TLS_CERT`, `ANGELSHARKD_TLS_KEY`,
+    /// and `ANGELSHARKD_TLS_CLIENT_CA`. `None` if TLS is not configured.
+    pub fn from_env() -> Result<Option<Self>> {
+        let client_ca = env::var_os("ANGELSHARKD_TLS_CLIENT_CA").map(PathBuf::from);
+        match (
+            env::var_os("ANGELSHARKD_TLS_CERT"),
+            env::var_os("ANGELSHARKD_TLS_KEY"),
+        ) {
+            (Some(cert), Some(key)) => Ok(Some(Self {
+                cert: cert.into(),
+                key: key.into(),
+                client_ca,
+            })),
+            (None, None) if client_ca.is_none() => Ok(None),
+            _ => bail!(
+                "TLS needs both a certificate and a key. Set ANGELSHARKD_TLS_CERT and ANGELSHARKD_TLS_KEY"
+            ),
+        }
+    }
+
+    /// Reads the files and checks that they make a valid TLS configuration.
+    pub fn read(&self) -> Result<TlsFiles> {
+        let read = |path: &PathBuf| {
+            fs::read(path).with_context(|| format!("Failed to read TLS file: {}", path.display()))
+        };
+        let files = TlsFiles {
+            cert: read(&self.cert)?,
+            key: read(&self.key)?,
+            client_ca: self.client_ca.as_ref().map(read).transpose()?,
+        };
+
+        // Note: warp panics on a bad TLS configuration when binding a server
+        // with it, so check the files the same way first.
+        let certs = |pem: &[u8], path: &PathBuf| -> Result<Vec<CertificateDer<'static>>> {
+            let certs = rustls_pemfile::certs(&mut &pem[..])
+                .collect::<Result<Vec<_>, _>>()
+                .with_context(|| format!("Failed to parse certificates in {}", path.display()))?;
+            if certs.is_empty() {
+                bail!("No certificates found in {}", path.display());
+            }
+            Ok(certs)
+        };
+        let cert = certs(&files.cert, &self.cert)?;
+        let key = match rustls_pemfile::private_key(&mut &files.key[..]) {
+            Ok(Some(key @ (PrivateKeyDer::Pkcs8(_) | PrivateKeyDer::Pkcs1(_)))) => key,
+            _ => bail!(
+                "No PKCS#8 or RSA private key found in {}",
+                self.key.display()
+            ),
+        };
+        let builder = ServerConfig::builder();
+        let builder = match (&self.client_ca, &files.client_ca) {
+            (Some(path), Some(client_ca)) => {
+                let mut roots = RootCertStore::empty();
+                for ca in certs(client_ca, path)? {
+                    roots
+                        .add(ca)
+                        .with_context(|| format!("Invalid client CA in {}", path.display()))?;
+                }
+                let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+                    .build()
+                    .with_context(|| format!("Invalid client CA in {}", path.display()))?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            _ => builder.with_no_client_auth(),
+        };
+        builder
+            .with_single_cert(cert, key)
+            .with_context(|| "Invalid TLS certificate or key.")?;
+        Ok(files)
+    }
+}
+
+impl TlsFiles {
+    /// Configures `server` with the files. Clients must present a certificate
+    /// signed by the client CA, if there is one.
+    pub fn apply<F>(&self, server: TlsServer<F>) -> TlsServer<F>
+    where
+        F: Filter<Error = Rejection> + Clone + Send + Sync + 'static,
+        F::Extract: Reply,
+    {
+        let server = server.cert(&self.cert).key(&self.key);
+        match &self.client_ca {
+            Some(client_ca) => server.client_auth_required(client_ca),
+            None => server,
+        }
+    }
+}
+
+/// SIGHUP, which reloads the TLS files. Never received where there is no
+/// SIGHUP.
+pub struct Hangups(#[cfg(unix)] signal::unix::Signal);
+
+impl Hangups {
+    pub fn new() -> Result<Self> {
+        #[cfg(unix)]
+        return signal::unix::signal(signal::unix::SignalKind::hangup())
+            .map(Self)
+            .with_context(|| "Failed to install SIGHUP signal handler.");
+        #[cfg(not(unix))]
+        Ok(Self())
+    }
+
+    /// Waits for the next SIGHUP.
+    pub async fn recv(&mut self) {
+        #[cfg(unix)]
+        self.0.recv().await;
+        #[cfg(not(unix))]
+        std::future::pending::<()>().await;
+    }
+}
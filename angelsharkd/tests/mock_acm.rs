@@ -0,0 +1,347 @@
+use angelsharkmock::{MockAcm, MockServer, TempFile};
+use rustls::{pki_types::ServerName, ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use std::{
+    env, fs,
+    io::{self, Read, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Hash of the API key `test-key`.
+const TEST_KEY_HASH: &str = "62af8704764faf8ea82fc61ce9c4c3908b6cb97d463a634e9e587d7c885db0ef";
+
+/// Hash of the API key `admin-key`.
+const ADMIN_KEY_HASH: &str = "69a5265506c94c77b787a7d7377b7685a0eff82e33920a71e7ee22cd6154953e";
+
+const TLS_CA: &str = include_str!("tls/ca.pem");
+const TLS_SERVER_CERT: &str = include_str!("tls/server.pem");
+const TLS_SERVER_KEY: &str = include_str!("tls/server.key");
+const TLS_CLIENT_CERT: &str = include_str!("tls/client.pem");
+const TLS_CLIENT_KEY: &str = include_str!("tls/client.key");
+
+/// Kills the daemon and removes its files when the test ends, even if it
+/// fails.
+struct Daemon {
+    child: Child,
+    port: u16,
+    files: Vec<TempFile>,
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl Daemon {
+    /// Starts the daemon in debug mode with a logins file for `server` named
+    /// CM01. `files` are written to temporary files, and each environment
+    /// variable in `files` is set to the path of its file. Each variable in
+    /// `vars` is set to its value.
+    fn start(server: &MockServer, files: &[(&str, &str)], vars: &[(&str, &str)]) -> Self {
+        let port = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let mut command = Command::new(env!("CARGO_BIN_EXE_angelsharkd"));
+        let logins = server.logins_file("CM01").unwrap();
+        command.env("ANGELSHARKD_LOGINS", logins.path());
+        let mut written = vec![logins];
+        for (var, contents) in files {
+            let file = TempFile::new(var, contents).unwrap();
+            command.env(var, file.path());
+            written.push(file);
+        }
+        command.envs(vars.iter().copied());
+
+        let child = command
+            .env("ANGELSHARKD_ADDR", format!("127.0.0.1:{}", port))
+            .env("ANGELSHARKD_DEBUG", "1")
+            .env("ANGELSHARKD_ORIGIN", "*")
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        Self {
+            child,
+            port,
+            files: written,
+        }
+    }
+
+    /// Returns the path of the file written for the environment variable
+    /// `var`.
+    fn file(&self, var: &str) -> PathBuf {
+        self.files
+            .iter()
+            .map(|file| file.path().to_path_buf())
+            .find(|path| path.to_string_lossy().ends_with(var))
+            .unwrap()
+    }
+
+    /// Sends a POST request with extra `headers` and returns the whole
+    /// response.
+    fn post(&self, path: &str, headers: &[&str], body: &str) -> String {
+        self.send("POST", path, headers, body)
+    }
+
+    /// Sends a GET request and returns the whole response.
+    fn get(&self, path: &str) -> String {
+        self.send("GET", path, &[], "")
+    }
+
+    fn send(&self, method: &str, path: &str, headers: &[&str], body: &str) -> String {
+        request(&mut self.connect(), method, path, headers, body).unwrap()
+    }
+
+    /// Sends a GET request over TLS, trusting the test CA, and presenting the
+    /// test client certificate if `client_cert`. Returns the whole response.
+    fn get_tls(&self, path: &str, client_cert: bool) -> io::Result<String> {
+        let pem = |contents: &str| -> Vec<_> {
+            rustls_pemfile::certs(&mut contents.as_bytes())
+                .map(Result::unwrap)
+                .collect()
+        };
+        let mut roots = RootCertStore::empty();
+        roots.add_parsable_certificates(pem(TLS_CA));
+        let config = ClientConfig::builder().with_root_certificates(roots);
+        let config = if client_cert {
+            let key = rustls_pemfile::private_key(&mut TLS_CLIENT_KEY.as_bytes());
+            config
+                .with_client_auth_cert(pem(TLS_CLIENT_CERT), key.unwrap().unwrap())
+                .unwrap()
+        } else {
+            config.with_no_client_auth()
+        };
+
+        let server_name = ServerName::try_from("localhost").unwrap();
+        let connection = ClientConnection::new(Arc::new(config), server_name).unwrap();
+        request(
+            &mut StreamOwned::new(connection, self.connect()),
+            "GET",
+            path,
+            &[],
+            "",
+        )
+    }
+
+    /// Connects to the daemon, waiting for it to start listening.
+    fn connect(&self) -> TcpStream {
+        let started = Instant::now();
+        loop {
+            match TcpStream::connect((Ipv4Addr::LOCALHOST, self.port)) {
+                Ok(stream) => break stream,
+                Err(_) if started.elapsed() < Duration::from_secs(10) => {
+                    thread::sleep(Duration::from_millis(50))
+                }
+                Err(e) => panic!("angelsharkd never started listening: {}", e),
+            }
+        }
+    }
+}
+
+/// Sends a request over `stream` and returns the whole response.
+fn request(
+    stream: &mut (impl Read + Write),
+    method: &str,
+    path: &str,
+    headers: &[&str],
+    body: &str,
+) -> io::Result<String> {
+    let headers: String = headers.iter().map(|h| format!("{}\r\n", h)).collect();
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        method,
+        path,
+        headers,
+        body.len(),
+        body
+    )?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    Ok(response)
+}
+
+const LIST_STATION: &str = r#"[{"acms":["CM01"],"command":"list station","fields":["8003ff00"]}]"#;
+
+#[test]
+fn ossi_runs_on_mock_acm() {
+    let server = MockAcm::stations().start().unwrap();
+    let daemon = Daemon::start(&server, &[], &[]);
+
+    let response = daemon.post("/ossi?no_cache=true", &[], LIST_STATION);
+
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    assert!(response.contains(
+        r#"{"acm":"CM01","command":"list station","error":"","fields":["8003ff00"],"datas":[["Carpenter, Adam"],["Doe, Jane"]]}"#
+    ));
+}
+
+#[test]
+fn api_keys_are_required() {
+    let server = MockAcm::stations().start().unwrap();
+    let keys = format!("# Test keys\nhelpdesk {}\n", TEST_KEY_HASH);
+    let daemon = Daemon::start(&server, &[("ANGELSHARKD_API_KEYS", &keys)], &[]);
+
+    let missing = daemon.post("/ossi", &[], LIST_STATION);
+    let invalid = daemon.post("/ossi", &["Authorization: Bearer wrong-key"], LIST_STATION);
+    let valid = daemon.post("/ossi", &["Authorization: Bearer test-key"], LIST_STATION);
+
+    assert!(missing.starts_with("HTTP/1.1 401 Unauthorized"));
+    assert!(missing.contains("www-authenticate: Bearer"));
+    assert!(invalid.starts_with("HTTP/1.1 401 Unauthorized"));
+    assert!(invalid.contains(r#"{"reason":"Invalid API key."}"#));
+    assert!(valid.starts_with("HTTP/1.1 200 OK"));
+    assert_eq!(server.logins(), 1);
+}
+
+#[test]
+fn policy_limits_commands_and_routes() {
+    let server = MockAcm::stations().start().unwrap();
+    let keys = format!("helpdesk {}\nadmin {}\n", TEST_KEY_HASH, ADMIN_KEY_HASH);
+    let policy = r#"
+        [roles.helpdesk]
+        keys = ["helpdesk"]
+        acms = ["cm0*"]
+        commands = ["LIST", "display", "status"]
+
+        [roles.admin]
+        keys = ["admin"]
+        acms = ["*"]
+        commands = ["*"]
+        extensions = ["*"]
+    "#;
+    let daemon = Daemon::start(
+        &server,
+        &[
+            ("ANGELSHARKD_API_KEYS", &keys),
+            ("ANGELSHARKD_POLICY", policy),
+        ],
+        &[],
+    );
+    let helpdesk = &["Authorization: Bearer test-key"];
+    let change = r#"[{"acms":["CM01"],"command":"list station"},{"acms":["CM01"],"command":"cha station 1000"}]"#;
+
+    let listed = daemon.post("/ossi?no_cache=true", helpdesk, LIST_STATION);
+    let changed = daemon.post("/ossi?no_cache=true", helpdesk, change);
+    let deprov = daemon.post("/extensions/deprov", helpdesk, "[]");
+    let index = daemon.post("/extensions", helpdesk, "");
+
+    assert!(listed.starts_with("HTTP/1.1 200 OK"));
+    assert!(changed.starts_with("HTTP/1.1 403 Forbidden"));
+    assert!(
+        changed.contains(r#""denied":[{"request":1,"acm":"CM01","command":"cha station 1000"}]"#)
+    );
+    assert!(deprov.starts_with("HTTP/1.1 403 Forbidden"));
+    assert!(deprov.contains(r#"{"reason":"Not allowed to use /extensions/deprov."}"#));
+    assert!(index.starts_with("HTTP/1.1 200 OK"));
+    assert_eq!(server.logins(), 1);
+
+    let admin = daemon.post(
+        "/ossi?dry_run=true",
+        &["Authorization: Bearer admin-key"],
+        change,
+    );
+    assert!(admin.starts_with("HTTP/1.1 200 OK"));
+}
+
+#[test]
+fn read_only_mode_refuses_changes() {
+    let server = MockAcm::stations().start().unwrap();
+    let daemon = Daemon::start(&server, &[], &[("ANGELSHARKD_READ_ONLY", "1")]);
+    let change = r#"[{"acms":["CM01"],"command":"list station"},{"acms":["CM01"],"command":"busy station 1000"},{"acms":["CM01"],"command":"monitor traffic trunk-groups"}]"#;
+
+    let version = daemon.get("/");
+    let listed = daemon.post("/ossi?no_cache=true", &[], LIST_STATION);
+    let changed = daemon.post("/ossi?no_cache=true", &[], change);
+    let deprov = daemon.post("/extensions/deprov", &[], "[]");
+
+    assert!(version.contains(r#""read_only":true"#));
+    assert!(listed.starts_with("HTTP/1.1 200 OK"));
+    assert!(changed.starts_with("HTTP/1.1 403 Forbidden"));
+    assert!(changed.contains(
+        r#""denied":[{"request":1,"acm":"CM01","command":"busy station 1000"},{"request":2,"acm":"CM01","command":"monitor traffic trunk-groups"}]"#
+    ));
+    assert!(deprov.starts_with("HTTP/1.1 403 Forbidden"));
+    assert!(deprov.contains("Read-only mode. /extensions/deprov is disabled."));
+    assert_eq!(server.logins(), 1);
+}
+
+#[test]
+fn audit_log_records_commands() {
+    let server = MockAcm::stations().start().unwrap();
+    let keys = format!("helpdesk {}\n", TEST_KEY_HASH);
+    let daemon = Daemon::start(
+        &server,
+        &[
+            ("ANGELSHARKD_API_KEYS", &keys),
+            ("ANGELSHARKD_AUDIT_LOG", ""),
+        ],
+        &[
+            ("ANGELSHARKD_AUDIT_MAX_SIZE", "1"),
+            ("ANGELSHARKD_AUDIT_KEEP", "1"),
+        ],
+    );
+    let helpdesk = &["Authorization: Bearer test-key"];
+    let unknown = r#"[{"acms":["CM99"],"command":"display station 1000"}]"#;
+
+    daemon.post("/ossi?no_cache=true", helpdesk, LIST_STATION);
+    daemon.post("/ossi?no_cache=true", helpdesk, unknown);
+
+    // Every entry is bigger than the maximum size, so the first was rotated.
+    let log = daemon.file("ANGELSHARKD_AUDIT_LOG");
+    let mut rotated = log.clone().into_os_string();
+    rotated.push(".1");
+    let first = fs::read_to_string(&rotated).unwrap();
+    let second = fs::read_to_string(&log).unwrap();
+    fs::remove_file(&rotated).unwrap();
+
+    assert_eq!(first.lines().count(), 1);
+    assert!(first.contains(r#""key":"helpdesk","addr":"127.0.0.1","route":"/ossi","acm":"CM01","command":"list station","fields":["8003ff00"],"datas":[],"error":null}"#));
+    assert_eq!(second.lines().count(), 1);
+    assert!(second.contains(r#""acm":"CM99","command":"display station 1000""#));
+    assert!(second.contains("No ACM is configured as 'CM99'."));
+}
+
+#[cfg(unix)]
+#[test]
+fn tls_requires_client_certs_and_reloads_on_hangup() {
+    let server = MockAcm::stations().start().unwrap();
+    let daemon = Daemon::start(
+        &server,
+        &[
+            ("ANGELSHARKD_TLS_CERT", TLS_SERVER_CERT),
+            ("ANGELSHARKD_TLS_KEY", TLS_SERVER_KEY),
+            ("ANGELSHARKD_TLS_CLIENT_CA", TLS_CA),
+        ],
+        &[],
+    );
+    let hangup = || {
+        Command::new("kill")
+            .args(["-HUP", &daemon.child.id().to_string()])
+            .status()
+            .unwrap();
+        thread::sleep(Duration::from_millis(500));
+    };
+    let served = |response: io::Result<String>| {
+        response.is_ok_and(|response| response.starts_with("HTTP/1.1 200 OK"))
+    };
+
+    assert!(served(daemon.get_tls("/", true)));
+    assert!(!served(daemon.get_tls("/", false)));
+    assert!(!served(request(&mut daemon.connect(), "GET", "/", &[], "")));
+
+    // Valid files are reloaded, and invalid ones are ignored.
+    hangup();
+    assert!(served(daemon.get_tls("/", true)));
+    fs::write(daemon.file("ANGELSHARKD_TLS_CERT"), "not a certificate").unwrap();
+    hangup();
+    assert!(served(daemon.get_tls("/", true)));
+}
@@ -0,0 +1,782 @@
+//! A mock Communication Manager for testing Angelshark without a real ACM. It
+//! listens on a local port for SSH connections, logs users in to an emulated
+//! Site Administration Terminal, and answers OSSI commands from a script.
+//!
+//! ```no_run
+//! use angelsharkmock::{MockAcm, Reply};
+//!
+//! let server = MockAcm::default()
+//!     .with_reply(
+//!         "list station",
+//!         Reply::data(&["8005ff00", "8003ff00"], &[&["17571230000", "Carpenter, Adam"]]),
+//!     )
+//!     .start()
+//!     .unwrap();
+//!
+//! // Connect to `server.addr()` as `mock` with password `mock`, or add
+//! // `server.login_line("CM01")` to a logins file.
+//! ```
+
+mod sat;
+mod ssh;
+
+use anyhow::{bail, Context, Result};
+use ed25519_dalek::SigningKey;
+use sat::{Action, Sat};
+use ssh::*;
+use std::{
+    collections::{HashMap, VecDeque},
+    env, fs,
+    net::{Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+const DEFAULT_USER: &str = "mock";
+const DEFAULT_PASS: &str = "mock";
+const DEFAULT_HOST_KEY: [u8; 32] = *b"angelshark mock acm host key 001";
+const WINDOW_SIZE: u32 = 2 * 1024 * 1024;
+const MAX_PACKET_SIZE: u32 = 32 * 1024;
+const MAX_LOGINS_MESSAGE: &str = "All SAT logins are in use. Try again later.\n";
+
+/// The OSSI error [MockAcm::stations] answers for a station that doesn't
+/// exist.
+pub const NOT_FOUND: &str = "1 00000000 29cf No records match the specified query options";
+
+/// Counts [TempFile]s, so that every one gets its own name.
+static TEMP_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// Configuration and script for a mock ACM. Start serving it with
+/// [Self::start].
+#[derive(Debug, Clone)]
+pub struct MockAcm {
+    user: String,
+    pass: String,
+    authorized_keys: Vec<String>,
+    host_key: [u8; 32],
+    max_logins: Option<usize>,
+    replies: HashMap<String, Reply>,
+    manuals: HashMap<String, String>,
+}
+
+impl Default for MockAcm {
+    fn default() -> Self {
+        Self {
+            user: DEFAULT_USER.into(),
+            pass: DEFAULT_PASS.into(),
+            authorized_keys: Vec::new(),
+            host_key: DEFAULT_HOST_KEY,
+            max_logins: None,
+            replies: HashMap::new(),
+            manuals: HashMap::new(),
+        }
+    }
+}
+
+/// The scripted answer to an OSSI command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reply {
+    /// Data entries for the given field addresses. If the command names
+    /// fields, only those are returned, in the order they were named.
+    Data {
+        fields: Vec<String>,
+        datas: Vec<Vec<String>>,
+    },
+    /// An OSSI error, such as
+    /// `1 00000000 29cf No records match the specified query options`.
+    Error(String),
+    /// Waits before answering, for testing timeouts.
+    Delayed(Duration, Box<Reply>),
+    /// Drops the connection instead of answering.
+    Disconnect,
+}
+
+impl Reply {
+    /// Data entries for the given field addresses.
+    pub fn data(fields: &[&str], datas: &[&[&str]]) -> Self {
+        Self::Data {
+            fields: fields.iter().map(|f| f.to_string()).collect(),
+            datas: datas
+                .iter()
+                .map(|data| data.iter().map(|d| d.to_string()).collect())
+                .collect(),
+        }
+    }
+
+    /// An empty, successful answer, like that of a change command.
+    pub fn ok() -> Self {
+        Self::data(&[], &[])
+    }
+
+    /// An OSSI error.
+    pub fn error(error: &str) -> Self {
+        Self::Error(error.into())
+    }
+
+    /// Waits for `delay` before answering with this reply.
+    pub fn delayed(self, delay: Duration) -> Self {
+        Self::Delayed(delay, Box::new(self))
+    }
+}
+
+/// An OSSI command received by a [MockServer].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Input {
+    pub command: String,
+    pub fields: Vec<String>,
+    pub datas: Vec<String>,
+}
+
+/// What a [MockServer] has seen so far.
+#[derive(Default)]
+struct State {
+    inputs: Vec<Input>,
+    logins: usize,
+    sessions: usize,
+    /// Open client connections, by the order they were accepted.
+    connections: HashMap<usize, TcpStream>,
+    accepted: usize,
+}
+
+impl MockAcm {
+    /// A mock ACM with two stations, as the Angelshark test suites use it:
+    ///
+    /// - `list station` lists `17571230000` (`Carpenter, Adam`) and
+    ///   `17571230001` (`Doe, Jane`) with fields `8005ff00` (extension) and
+    ///   `8003ff00` (name).
+    /// - `display station 17571239999` fails with [NOT_FOUND].
+    /// - `change station 17571230000` succeeds.
+    pub fn stations() -> Self {
+        let mut acm = Self::default();
+        acm.with_reply(
+            "list station",
+            Reply::data(
+                &["8005ff00", "8003ff00"],
+                &[
+                    &["17571230000", "Carpenter, Adam"],
+                    &["17571230001", "Doe, Jane"],
+                ],
+            ),
+        )
+        .with_reply("display station 17571239999", Reply::error(NOT_FOUND))
+        .with_reply("change station 17571230000", Reply::ok());
+        acm
+    }
+
+    /// Sets the username and password that are accepted. The default is
+    /// `mock` and `mock`.
+    pub fn with_login(&mut self, user: &str, pass: &str) -> &mut Self {
+        self.user = user.into();
+        self.pass = pass.into();
+        self
+    }
+
+    /// Also accepts logins as the user with the public key in `key`, an
+    /// OpenSSH public key line such as `ssh-ed25519 AAAA... comment`. Only
+    /// ssh-ed25519 keys are supported.
+    pub fn with_authorized_key(&mut self, key: &str) -> &mut Self {
+        self.authorized_keys.push(key.into());
+        self
+    }
+
+    /// Sets the seed of the ed25519 host key. Servers with the same seed have
+    /// the same host key.
+    pub fn with_host_key(&mut self, seed: [u8; 32]) -> &mut Self {
+        self.host_key = seed;
+        self
+    }
+
+    /// Limits how many SAT sessions may be open at once. Logins beyond this
+    /// are told that SAT is out of logins and never reach the OSSI prompt.
+    pub fn with_max_logins(&mut self, max_logins: usize) -> &mut Self {
+        self.max_logins = Some(max_logins);
+        self
+    }
+
+    /// Answers `command` with `reply`. Commands must match exactly (no
+    /// shorthands). Unscripted commands get an OSSI error.
+    pub fn with_reply(&mut self, command: &str, reply: Reply) -> &mut Self {
+        self.replies.insert(command.into(), reply);
+        self
+    }
+
+    /// Answers `command` on the `ossiem` (manual) terminal with `page`.
+    pub fn with_manual(&mut self, command: &str, page: &str) -> &mut Self {
+        self.manuals.insert(command.into(), page.into());
+        self
+    }
+
+    /// Whether `key`, an SSH public key blob, is one of the authorized keys.
+    fn is_authorized(&self, key: &[u8]) -> bool {
+        self.authorized_keys.iter().any(|line| {
+            line.split_whitespace()
+                .nth(1)
+                .and_then(|blob| base64::decode(blob).ok())
+                .is_some_and(|blob| blob == key)
+        })
+    }
+
+    /// Starts serving on a free port on 127.0.0.1 in the background. The
+    /// server stops when the returned [MockServer] is dropped.
+    pub fn start(&self) -> Result<MockServer> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .with_context(|| "Failed to bind mock ACM listener.")?;
+        let addr = listener.local_addr()?;
+        let acm = Arc::new(self.clone());
+        let state = Arc::new(Mutex::new(State::default()));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let accept = {
+            let (acm, state, stop) = (acm.clone(), state.clone(), stop.clone());
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    if let Ok(stream) = stream {
+                        let (acm, state) = (acm.clone(), state.clone());
+                        thread::spawn(move || {
+                            let _ = serve(stream, acm, state);
+                        });
+                    }
+                }
+            })
+        };
+
+        Ok(MockServer {
+            addr,
+            acm,
+            state,
+            stop,
+            accept: Some(accept),
+        })
+    }
+}
+
+/// A running mock ACM.
+pub struct MockServer {
+    addr: SocketAddr,
+    acm: Arc<MockAcm>,
+    state: Arc<Mutex<State>>,
+    stop: Arc<AtomicBool>,
+    accept: Option<JoinHandle<()>>,
+}
+
+impl MockServer {
+    /// The address the server is listening on.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Returns an `asa.cfg` login line for this server, named `name`. Host key
+    /// verification is turned off, since the server is local.
+    pub fn login_line(&self, name: &str) -> String {
+        format!(
+            "{} {}:{}@{} hostkey=off",
+            name, self.acm.user, self.acm.pass, self.addr
+        )
+    }
+
+    /// Writes an `asa.cfg` logins file with [Self::login_line], for programs
+    /// under test to read. It is removed when dropped.
+    pub fn logins_file(&self, name: &str) -> Result<TempFile> {
+        TempFile::new("asa.cfg", self.login_line(name))
+    }
+
+    /// Every OSSI command received so far (except logoffs), in order.
+    pub fn inputs(&self) -> Vec<Input> {
+        self.lock().inputs.clone()
+    }
+
+    /// How many times a client has logged in successfully.
+    pub fn logins(&self) -> usize {
+        self.lock().logins
+    }
+
+    /// How many SAT sessions are open right now.
+    pub fn sessions(&self) -> usize {
+        self.lock().sessions
+    }
+
+    /// Drops every open connection without logging off, like an ACM that
+    /// restarts or a firewall that forgets idle connections.
+    pub fn disconnect(&self) {
+        for (_, stream) in self.lock().connections.drain() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Drop for MockServer {
+    /// Stops accepting connections. Connections already open are left to
+    /// finish on their own.
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        let _ = TcpStream::connect(self.addr);
+        if let Some(accept) = self.accept.take() {
+            let _ = accept.join();
+        }
+    }
+}
+
+/// A file in the temporary directory, such as a logins file, that is removed
+/// when dropped.
+pub struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    /// Writes `contents` to a new file whose name ends with `name`.
+    pub fn new(name: &str, contents: impl AsRef<[u8]>) -> Result<Self> {
+        let path = env::temp_dir().join(format!(
+            "angelshark-{}-{}-{}",
+            process::id(),
+            TEMP_FILES.fetch_add(1, Ordering::SeqCst),
+            name
+        ));
+        fs::write(&path, contents)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(Self { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Handles one client connection until it is closed.
+fn serve(stream: TcpStream, acm: Arc<MockAcm>, state: Arc<Mutex<State>>) -> Result<()> {
+    stream.set_nodelay(true)?;
+    let id = {
+        let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+        state.accepted += 1;
+        let id = state.accepted;
+        state.connections.insert(id, stream.try_clone()?);
+        id
+    };
+    let result = serve_connection(stream, acm, state.clone());
+    state
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .connections
+        .remove(&id);
+    result
+}
+
+/// Logs the client in and runs its connection until it is closed.
+fn serve_connection(stream: TcpStream, acm: Arc<MockAcm>, state: Arc<Mutex<State>>) -> Result<()> {
+    let host_key = SigningKey::from_bytes(&acm.host_key);
+    let mut transport = Transport::accept(stream, &host_key)?;
+    authenticate(&mut transport, &acm)?;
+    state.lock().unwrap_or_else(|e| e.into_inner()).logins += 1;
+
+    let mut connection = Connection {
+        transport,
+        acm,
+        state,
+        channel: None,
+        pending: VecDeque::new(),
+    };
+    let result = connection.run();
+    if connection.channel.as_ref().is_some_and(|c| c.sat.is_some()) {
+        connection.lock().sessions -= 1;
+    }
+    result
+}
+
+/// Runs the user authentication service until the client logs in as the
+/// configured user, either with the password (by the password method or
+/// keyboard-interactive) or with an authorized public key.
+fn authenticate(transport: &mut Transport, acm: &MockAcm) -> Result<()> {
+    loop {
+        let packet = transport.read_packet()?;
+        let mut reader = Reader::new(&packet);
+
+        match reader.byte()? {
+            MSG_SERVICE_REQUEST => {
+                let service = reader.string()?;
+                if service != b"ssh-userauth" {
+                    bail!("Client requested an unknown service.");
+                }
+                transport.write_packet(
+                    &Writer::new()
+                        .byte(MSG_SERVICE_ACCEPT)
+                        .string(service)
+                        .finish(),
+                )?;
+            }
+            MSG_USERAUTH_REQUEST => {
+                let user = reader.utf8()?;
+                let service = reader.string()?;
+                let pass = match reader.string()? {
+                    b"password" => {
+                        reader.bool()?;
+                        Some(reader.utf8()?)
+                    }
+                    b"publickey" => {
+                        let signed = reader.bool()?;
+                        let algorithm = reader.string()?;
+                        let key = reader.string()?;
+                        if user == acm.user && acm.is_authorized(key) {
+                            if !signed {
+                                transport.write_packet(
+                                    &Writer::new()
+                                        .byte(MSG_USERAUTH_PK_OK)
+                                        .string(algorithm)
+                                        .string(key)
+                                        .finish(),
+                                )?;
+                                continue;
+                            }
+                            let data = Writer::new()
+                                .string(&transport.session_id)
+                                .byte(MSG_USERAUTH_REQUEST)
+                                .string(user.as_bytes())
+                                .string(service)
+                                .string(b"publickey")
+                                .bool(true)
+                                .string(algorithm)
+                                .string(key)
+                                .finish();
+                            if verify_signature(key, &data, reader.string()?) {
+                                transport.write_packet(&[MSG_USERAUTH_SUCCESS])?;
+                                return Ok(());
+                            }
+                        }
+                        None
+                    }
+                    b"keyboard-interactive" => {
+                        transport.write_packet(
+                            &Writer::new()
+                                .byte(MSG_USERAUTH_INFO_REQUEST)
+                                .string(b"")
+                                .string(b"")
+                                .string(b"")
+                                .u32(1)
+                                .string(b"Password: ")
+                                .bool(false)
+                                .finish(),
+                        )?;
+                        let response = transport.read_packet()?;
+                        let mut reader = Reader::new(&response);
+                        if reader.byte()? != MSG_USERAUTH_INFO_RESPONSE || reader.u32()? == 0 {
+                            None
+                        } else {
+                            Some(reader.utf8()?)
+                        }
+                    }
+                    _ => None,
+                };
+
+                if user == acm.user && pass.as_deref() == Some(acm.pass.as_str()) {
+                    transport.write_packet(&[MSG_USERAUTH_SUCCESS])?;
+                    return Ok(());
+                }
+                transport.write_packet(
+                    &Writer::new()
+                        .byte(MSG_USERAUTH_FAILURE)
+                        .string(b"publickey,keyboard-interactive,password")
+                        .bool(false)
+                        .finish(),
+                )?;
+            }
+            _ => bail!("Client sent an unexpected message before logging in."),
+        }
+    }
+}
+
+/// A logged-in connection with at most one session channel.
+struct Connection {
+    transport: Transport,
+    acm: Arc<MockAcm>,
+    state: Arc<Mutex<State>>,
+    channel: Option<Channel>,
+    /// Data received while waiting to send, handled afterwards.
+    pending: VecDeque<Vec<u8>>,
+}
+
+struct Channel {
+    remote_id: u32,
+    remote_window: u32,
+    remote_max_packet: u32,
+    local_window: u32,
+    sat: Option<Sat>,
+    closed: bool,
+}
+
+impl Connection {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn run(&mut self) -> Result<()> {
+        loop {
+            while let Some(data) = self.pending.pop_front() {
+                if !self.feed(&data)? {
+                    return Ok(());
+                }
+            }
+
+            let packet = self.transport.read_packet()?;
+            if !self.handle(&packet)? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Handles one packet. Returns whether the connection should stay open.
+    fn handle(&mut self, packet: &[u8]) -> Result<bool> {
+        let mut reader = Reader::new(packet);
+
+        match reader.byte()? {
+            MSG_GLOBAL_REQUEST => {
+                reader.string()?;
+                if reader.bool()? {
+                    self.transport.write_packet(&[MSG_REQUEST_FAILURE])?;
+                }
+            }
+            MSG_CHANNEL_OPEN => {
+                let kind = reader.string()?;
+                let remote_id = reader.u32()?;
+                let remote_window = reader.u32()?;
+                let remote_max_packet = reader.u32()?;
+
+                if kind == b"session" && self.channel.is_none() {
+                    self.channel = Some(Channel {
+                        remote_id,
+                        remote_window,
+                        remote_max_packet,
+                        local_window: WINDOW_SIZE,
+                        sat: None,
+                        closed: false,
+                    });
+                    self.transport.write_packet(
+                        &Writer::new()
+                            .byte(MSG_CHANNEL_OPEN_CONFIRMATION)
+                            .u32(remote_id)
+                            .u32(0)
+                            .u32(WINDOW_SIZE)
+                            .u32(MAX_PACKET_SIZE)
+                            .finish(),
+                    )?;
+                } else {
+                    self.transport.write_packet(
+                        &Writer::new()
+                            .byte(MSG_CHANNEL_OPEN_FAILURE)
+                            .u32(remote_id)
+                            .u32(1)
+                            .string(b"Only one session channel is supported.")
+                            .string(b"")
+                            .finish(),
+                    )?;
+                }
+            }
+            MSG_CHANNEL_REQUEST => {
+                reader.u32()?;
+                let kind = reader.string()?;
+                let want_reply = reader.bool()?;
+                let Some(channel) = &mut self.channel else {
+                    bail!("Client sent a request without a channel.");
+                };
+
+                let (ok, start) = match kind {
+                    b"pty-req" | b"env" => (true, false),
+                    b"shell" if channel.sat.is_none() => (true, true),
+                    _ => (false, false),
+                };
+                if want_reply {
+                    let reply = if ok {
+                        MSG_CHANNEL_SUCCESS
+                    } else {
+                        MSG_CHANNEL_FAILURE
+                    };
+                    let remote_id = channel.remote_id;
+                    self.transport
+                        .write_packet(&Writer::new().byte(reply).u32(remote_id).finish())?;
+                }
+                if start {
+                    return self.start_sat();
+                }
+            }
+            MSG_CHANNEL_DATA => {
+                reader.u32()?;
+                let data = reader.string()?.to_vec();
+                return self.feed(&data);
+            }
+            MSG_CHANNEL_WINDOW_ADJUST => {
+                reader.u32()?;
+                let add = reader.u32()?;
+                if let Some(channel) = &mut self.channel {
+                    channel.remote_window = channel.remote_window.saturating_add(add);
+                }
+            }
+            MSG_CHANNEL_EXTENDED_DATA | MSG_CHANNEL_EOF => {}
+            MSG_CHANNEL_CLOSE => {
+                if let Some(channel) = &mut self.channel {
+                    if !channel.closed {
+                        channel.closed = true;
+                        let remote_id = channel.remote_id;
+                        self.transport.write_packet(
+                            &Writer::new()
+                                .byte(MSG_CHANNEL_CLOSE)
+                                .u32(remote_id)
+                                .finish(),
+                        )?;
+                    }
+                }
+                return Ok(false);
+            }
+            _ => {}
+        }
+
+        Ok(true)
+    }
+
+    /// Starts SAT on the channel's shell, unless it is out of logins.
+    fn start_sat(&mut self) -> Result<bool> {
+        {
+            let mut state = self.lock();
+            if self.acm.max_logins.is_some_and(|max| state.sessions >= max) {
+                drop(state);
+                return self.perform(vec![Action::Send(MAX_LOGINS_MESSAGE.into()), Action::Close]);
+            }
+            state.sessions += 1;
+        }
+
+        let (sat, actions) = Sat::start(self.acm.clone(), self.state.clone());
+        if let Some(channel) = &mut self.channel {
+            channel.sat = Some(sat);
+        }
+        self.perform(actions)
+    }
+
+    /// Feeds data from the client to SAT. Returns whether the connection
+    /// should stay open.
+    fn feed(&mut self, data: &[u8]) -> Result<bool> {
+        let Some(channel) = &mut self.channel else {
+            return Ok(true);
+        };
+
+        channel.local_window = channel.local_window.saturating_sub(data.len() as u32);
+        if channel.local_window < WINDOW_SIZE / 2 {
+            let add = WINDOW_SIZE - channel.local_window;
+            channel.local_window = WINDOW_SIZE;
+            let remote_id = channel.remote_id;
+            self.transport.write_packet(
+                &Writer::new()
+                    .byte(MSG_CHANNEL_WINDOW_ADJUST)
+                    .u32(remote_id)
+                    .u32(add)
+                    .finish(),
+            )?;
+        }
+
+        let actions = match self.channel.as_mut().and_then(|c| c.sat.as_mut()) {
+            Some(sat) => sat.feed(data),
+            None => Vec::new(),
+        };
+        self.perform(actions)
+    }
+
+    /// Carries out SAT's actions. Returns whether the connection should stay
+    /// open.
+    fn perform(&mut self, actions: Vec<Action>) -> Result<bool> {
+        for action in actions {
+            match action {
+                Action::Send(data) => self.send(&data)?,
+                Action::Sleep(delay) => thread::sleep(delay),
+                Action::Close => self.close()?,
+                Action::Disconnect => return Ok(false),
+            }
+        }
+        Ok(true)
+    }
+
+    /// Sends `data` on the channel, waiting for the client to open its window
+    /// when needed.
+    fn send(&mut self, mut data: &[u8]) -> Result<()> {
+        while !data.is_empty() {
+            let Some(channel) = &mut self.channel else {
+                return Ok(());
+            };
+            if channel.closed {
+                return Ok(());
+            }
+
+            if channel.remote_window == 0 {
+                let packet = self.transport.read_packet()?;
+                let mut reader = Reader::new(&packet);
+                match reader.byte()? {
+                    MSG_CHANNEL_DATA => {
+                        reader.u32()?;
+                        self.pending.push_back(reader.string()?.to_vec());
+                    }
+                    _ => {
+                        if !self.handle(&packet)? {
+                            bail!("Client closed the channel while receiving data.");
+                        }
+                    }
+                }
+                continue;
+            }
+
+            let len = data
+                .len()
+                .min(channel.remote_window as usize)
+                .min(channel.remote_max_packet.min(MAX_PACKET_SIZE) as usize - 64);
+            channel.remote_window -= len as u32;
+            let remote_id = channel.remote_id;
+            self.transport.write_packet(
+                &Writer::new()
+                    .byte(MSG_CHANNEL_DATA)
+                    .u32(remote_id)
+                    .string(&data[..len])
+                    .finish(),
+            )?;
+            data = &data[len..];
+        }
+
+        Ok(())
+    }
+
+    /// Closes the channel from our side, like SAT does after a logoff.
+    fn close(&mut self) -> Result<()> {
+        let Some(channel) = &mut self.channel else {
+            return Ok(());
+        };
+        if channel.closed {
+            return Ok(());
+        }
+        channel.closed = true;
+        let remote_id = channel.remote_id;
+
+        // Free the SAT session before the client can see it end.
+        if channel.sat.take().is_some() {
+            self.lock().sessions -= 1;
+        }
+        self.transport
+            .write_packet(&Writer::new().byte(MSG_CHANNEL_EOF).u32(remote_id).finish())?;
+        self.transport.write_packet(
+            &Writer::new()
+                .byte(MSG_CHANNEL_CLOSE)
+                .u32(remote_id)
+                .finish(),
+        )
+    }
+}
@@ -0,0 +1,196 @@
+//! Emulates SAT's OSSI terminals on an already open SSH shell. SAT asks for a
+//! terminal type, and `ossie` or `ossiem` answers with the OSSI terminator.
+//! After that, each command is read up to its terminator and answered from
+//! the script.
+
+use crate::{Input, MockAcm, Reply, State};
+use std::{
+    mem,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+const TERM_PROMPT: &str = "Terminal Type (513, 715, 4410, 4425, VT220, NTT, W2KTT, SUNT): [513]\n";
+const LOGOFF_PROMPT: &str = "Proceed With Logoff? [n]\n";
+const UNKNOWN_COMMAND: &str = "1 00000000 2ee2 Command not recognized by mock ACM";
+const NO_MANUAL: &str = "1 00000000 2ee2 No manual page in mock ACM";
+
+/// What the SSH side should do next.
+pub(crate) enum Action {
+    Send(Vec<u8>),
+    Sleep(Duration),
+    /// Closes the channel cleanly, like a finished logoff.
+    Close,
+    /// Drops the connection without closing the channel.
+    Disconnect,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Terminal,
+    Ossi,
+    Manual,
+    Logoff(bool),
+}
+
+/// One SAT session.
+pub(crate) struct Sat {
+    acm: Arc<MockAcm>,
+    state: Arc<Mutex<State>>,
+    mode: Mode,
+    line: Vec<u8>,
+    input: Input,
+}
+
+impl Sat {
+    /// Starts a session, prompting for a terminal type.
+    pub(crate) fn start(acm: Arc<MockAcm>, state: Arc<Mutex<State>>) -> (Self, Vec<Action>) {
+        let sat = Self {
+            acm,
+            state,
+            mode: Mode::Terminal,
+            line: Vec::new(),
+            input: Input::default(),
+        };
+        (sat, vec![Action::Send(TERM_PROMPT.into())])
+    }
+
+    /// Handles data typed by the client.
+    pub(crate) fn feed(&mut self, data: &[u8]) -> Vec<Action> {
+        let mut actions = Vec::new();
+
+        for byte in data {
+            if *byte == b'\n' {
+                let line = String::from_utf8_lossy(&mem::take(&mut self.line)).into_owned();
+                actions.extend(self.line(line.trim_end_matches('\r')));
+            } else {
+                self.line.push(*byte);
+            }
+        }
+
+        actions
+    }
+
+    fn line(&mut self, line: &str) -> Vec<Action> {
+        match self.mode {
+            Mode::Terminal => match line {
+                "ossie" => self.enter(Mode::Ossi),
+                "ossiem" => self.enter(Mode::Manual),
+                _ => vec![Action::Send(TERM_PROMPT.into())],
+            },
+            Mode::Logoff(_) if line.starts_with('y') => vec![Action::Close],
+            Mode::Logoff(manual) => self.enter(if manual { Mode::Manual } else { Mode::Ossi }),
+            Mode::Ossi | Mode::Manual => {
+                let (delim, content) = (line.get(..1).unwrap_or_default(), line.get(1..));
+                let values = || content.unwrap_or_default().split('\t').map(String::from);
+
+                match delim {
+                    "c" => {
+                        self.input = Input {
+                            command: content.unwrap_or_default().trim().into(),
+                            ..Default::default()
+                        }
+                    }
+                    "f" => self.input.fields.extend(values()),
+                    "d" => self.input.datas.extend(values()),
+                    "t" => return self.terminate(),
+                    _ => {}
+                }
+                Vec::new()
+            }
+        }
+    }
+
+    fn enter(&mut self, mode: Mode) -> Vec<Action> {
+        self.mode = mode;
+        vec![Action::Send(b"t\n".to_vec())]
+    }
+
+    /// Answers the command that was just terminated.
+    fn terminate(&mut self) -> Vec<Action> {
+        let input = mem::take(&mut self.input);
+        let manual = self.mode == Mode::Manual;
+
+        if input.command == "logoff" {
+            self.mode = Mode::Logoff(manual);
+            return vec![Action::Send(LOGOFF_PROMPT.into())];
+        }
+
+        self.state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .inputs
+            .push(input.clone());
+
+        if manual {
+            let output = match self.acm.manuals.get(&input.command) {
+                Some(page) if page.ends_with('\n') => page.clone(),
+                Some(page) => format!("{}\n", page),
+                None => render(&input, &Reply::Error(NO_MANUAL.into())),
+            };
+            vec![Action::Send(output.into_bytes())]
+        } else {
+            let reply = self
+                .acm
+                .replies
+                .get(&input.command)
+                .cloned()
+                .unwrap_or_else(|| Reply::Error(UNKNOWN_COMMAND.into()));
+            actions(&input, &reply)
+        }
+    }
+}
+
+fn actions(input: &Input, reply: &Reply) -> Vec<Action> {
+    match reply {
+        Reply::Delayed(delay, reply) => {
+            let mut actions = vec![Action::Sleep(*delay)];
+            actions.extend(self::actions(input, reply));
+            actions
+        }
+        Reply::Disconnect => vec![Action::Disconnect],
+        reply => vec![Action::Send(render(input, reply).into_bytes())],
+    }
+}
+
+/// Formats `reply` as OSSI output. Like SAT, only the requested fields are
+/// returned when the input names any.
+fn render(input: &Input, reply: &Reply) -> String {
+    let mut output = format!("c{}\n", input.command);
+
+    match reply {
+        Reply::Data { fields, datas } => {
+            let columns: Vec<usize> = if input.fields.is_empty() {
+                (0..fields.len()).collect()
+            } else {
+                input
+                    .fields
+                    .iter()
+                    .filter_map(|f| fields.iter().position(|field| field == f))
+                    .collect()
+            };
+            let select = |values: &[String]| {
+                columns
+                    .iter()
+                    .map(|c| values.get(*c).map(String::as_str).unwrap_or_default())
+                    .collect::<Vec<_>>()
+                    .join("\t")
+            };
+
+            if !columns.is_empty() {
+                output.push_str(&format!("f{}\n", select(fields)));
+            }
+            for (i, data) in datas.iter().enumerate() {
+                if i > 0 {
+                    output.push_str("n\n");
+                }
+                output.push_str(&format!("d{}\n", select(data)));
+            }
+        }
+        Reply::Error(error) => output.push_str(&format!("e{}\n", error)),
+        Reply::Delayed(_, _) | Reply::Disconnect => {}
+    }
+
+    output.push_str("t\n");
+    output
+}
@@ -0,0 +1,460 @@
+//! Just enough of the SSH transport protocol (RFC 4253) to talk to libssh2:
+//! curve25519-sha256 key exchange, an ssh-ed25519 host key, aes128-ctr
+//! encryption, and hmac-sha2-256 integrity.
+
+use aes::Aes128;
+use anyhow::{anyhow, bail, Context, Result};
+use ctr::{
+    cipher::{KeyIvInit, StreamCipher},
+    Ctr128BE,
+};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use hmac::{Hmac, Mac};
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+};
+use x25519_dalek::{EphemeralSecret, PublicKey};
+
+const SERVER_VERSION: &str = "SSH-2.0-AngelsharkMock_0.1";
+const KEX_ALGORITHMS: &str = "curve25519-sha256,curve25519-sha256@libssh.org";
+const HOST_KEY_ALGORITHM: &str = "ssh-ed25519";
+const CIPHER: &str = "aes128-ctr";
+const MAC: &str = "hmac-sha2-256";
+const COMPRESSION: &str = "none";
+const MAX_PACKET: usize = 256 * 1024;
+const MAC_LEN: usize = 32;
+
+pub(crate) const MSG_DISCONNECT: u8 = 1;
+pub(crate) const MSG_IGNORE: u8 = 2;
+pub(crate) const MSG_UNIMPLEMENTED: u8 = 3;
+pub(crate) const MSG_DEBUG: u8 = 4;
+pub(crate) const MSG_SERVICE_REQUEST: u8 = 5;
+pub(crate) const MSG_SERVICE_ACCEPT: u8 = 6;
+const MSG_KEXINIT: u8 = 20;
+const MSG_NEWKEYS: u8 = 21;
+const MSG_KEX_ECDH_INIT: u8 = 30;
+const MSG_KEX_ECDH_REPLY: u8 = 31;
+pub(crate) const MSG_USERAUTH_REQUEST: u8 = 50;
+pub(crate) const MSG_USERAUTH_FAILURE: u8 = 51;
+pub(crate) const MSG_USERAUTH_SUCCESS: u8 = 52;
+pub(crate) const MSG_USERAUTH_PK_OK: u8 = 60;
+pub(crate) const MSG_USERAUTH_INFO_REQUEST: u8 = 60;
+pub(crate) const MSG_USERAUTH_INFO_RESPONSE: u8 = 61;
+pub(crate) const MSG_GLOBAL_REQUEST: u8 = 80;
+pub(crate) const MSG_REQUEST_FAILURE: u8 = 82;
+pub(crate) const MSG_CHANNEL_OPEN: u8 = 90;
+pub(crate) const MSG_CHANNEL_OPEN_CONFIRMATION: u8 = 91;
+pub(crate) const MSG_CHANNEL_OPEN_FAILURE: u8 = 92;
+pub(crate) const MSG_CHANNEL_WINDOW_ADJUST: u8 = 93;
+pub(crate) const MSG_CHANNEL_DATA: u8 = 94;
+pub(crate) const MSG_CHANNEL_EXTENDED_DATA: u8 = 95;
+pub(crate) const MSG_CHANNEL_EOF: u8 = 96;
+pub(crate) const MSG_CHANNEL_CLOSE: u8 = 97;
+pub(crate) const MSG_CHANNEL_REQUEST: u8 = 98;
+pub(crate) const MSG_CHANNEL_SUCCESS: u8 = 99;
+pub(crate) const MSG_CHANNEL_FAILURE: u8 = 100;
+
+/// An encrypted, authenticated SSH connection from a client, after key
+/// exchange.
+pub(crate) struct Transport {
+    stream: TcpStream,
+    /// The exchange hash of the key exchange, which clients sign to log in
+    /// with a public key.
+    pub(crate) session_id: Vec<u8>,
+    recv: Direction,
+    send: Direction,
+}
+
+/// Packet state for one direction of the connection.
+#[derive(Default)]
+struct Direction {
+    seq: u32,
+    keys: Option<Keys>,
+}
+
+struct Keys {
+    cipher: Ctr128BE<Aes128>,
+    mac: Hmac<Sha256>,
+}
+
+impl Transport {
+    /// Exchanges versions and keys with the client on `stream`, proving our
+    /// identity with `host_key`.
+    pub(crate) fn accept(stream: TcpStream, host_key: &SigningKey) -> Result<Self> {
+        let mut transport = Self {
+            stream,
+            session_id: Vec::new(),
+            recv: Direction::default(),
+            send: Direction::default(),
+        };
+
+        transport
+            .stream
+            .write_all(format!("{}\r\n", SERVER_VERSION).as_bytes())
+            .with_context(|| "Failed to send server version.")?;
+        let client_version = transport.read_version()?;
+
+        let server_kexinit = kexinit();
+        transport.write_packet(&server_kexinit)?;
+        let client_kexinit = transport.read_packet()?;
+        if client_kexinit.first() != Some(&MSG_KEXINIT) {
+            bail!("Client did not start key exchange.");
+        }
+        check_algorithms(&client_kexinit)?;
+
+        let ecdh_init = transport.read_packet()?;
+        let mut reader = Reader::new(&ecdh_init);
+        if reader.byte()? != MSG_KEX_ECDH_INIT {
+            bail!("Client did not send its key exchange key.");
+        }
+        let client_public: [u8; 32] = reader
+            .string()?
+            .try_into()
+            .map_err(|_| anyhow!("Client key exchange key is the wrong length."))?;
+
+        let secret = EphemeralSecret::random_from_rng(OsRng);
+        let server_public = PublicKey::from(&secret);
+        let shared = secret.diffie_hellman(&PublicKey::from(client_public));
+        let shared = Writer::new().mpint(shared.as_bytes()).finish();
+
+        let host_key_blob = Writer::new()
+            .string(HOST_KEY_ALGORITHM.as_bytes())
+            .string(host_key.verifying_key().as_bytes())
+            .finish();
+        let exchange_hash: Vec<u8> = Sha256::new()
+            .chain_update(Writer::new().string(client_version.as_bytes()).finish())
+            .chain_update(Writer::new().string(SERVER_VERSION.as_bytes()).finish())
+            .chain_update(Writer::new().string(&client_kexinit).finish())
+            .chain_update(Writer::new().string(&server_kexinit).finish())
+            .chain_update(Writer::new().string(&host_key_blob).finish())
+            .chain_update(Writer::new().string(&client_public).finish())
+            .chain_update(Writer::new().string(server_public.as_bytes()).finish())
+            .chain_update(&shared)
+            .finalize()
+            .to_vec();
+        let signature = Writer::new()
+            .string(HOST_KEY_ALGORITHM.as_bytes())
+            .string(&host_key.sign(&exchange_hash).to_bytes())
+            .finish();
+
+        transport.write_packet(
+            &Writer::new()
+                .byte(MSG_KEX_ECDH_REPLY)
+                .string(&host_key_blob)
+                .string(server_public.as_bytes())
+                .string(&signature)
+                .finish(),
+        )?;
+        transport.write_packet(&[MSG_NEWKEYS])?;
+        transport.send.keys = Some(Keys::derive(&shared, &exchange_hash, b'B', b'D', b'F'));
+
+        if transport.read_packet()?.first() != Some(&MSG_NEWKEYS) {
+            bail!("Client did not finish key exchange.");
+        }
+        transport.recv.keys = Some(Keys::derive(&shared, &exchange_hash, b'A', b'C', b'E'));
+        transport.session_id = exchange_hash;
+
+        Ok(transport)
+    }
+
+    /// Reads the client's version line, skipping any lines before it.
+    fn read_version(&mut self) -> Result<String> {
+        loop {
+            let mut line = Vec::new();
+            let mut byte = [0];
+            while byte[0] != b'\n' {
+                self.stream
+                    .read_exact(&mut byte)
+                    .with_context(|| "Failed to read client version.")?;
+                line.push(byte[0]);
+                if line.len() > 255 {
+                    bail!("Client version is too long.");
+                }
+            }
+
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches('\n').trim_end_matches('\r');
+            if line.starts_with("SSH-2.0-") {
+                return Ok(line.into());
+            }
+        }
+    }
+
+    /// Reads the payload of the next packet, skipping ignore and debug
+    /// messages.
+    pub(crate) fn read_packet(&mut self) -> Result<Vec<u8>> {
+        loop {
+            let payload = self.read_any_packet()?;
+            match payload.first() {
+                Some(&MSG_IGNORE) | Some(&MSG_DEBUG) | Some(&MSG_UNIMPLEMENTED) => continue,
+                Some(&MSG_DISCONNECT) => bail!("Client disconnected."),
+                Some(_) => return Ok(payload),
+                None => bail!("Client sent an empty packet."),
+            }
+        }
+    }
+
+    fn read_any_packet(&mut self) -> Result<Vec<u8>> {
+        let block = if self.recv.keys.is_some() { 16 } else { 8 };
+        let mut packet = vec![0; block];
+        self.stream
+            .read_exact(&mut packet)
+            .with_context(|| "Failed to read packet.")?;
+        if let Some(keys) = &mut self.recv.keys {
+            keys.cipher.apply_keystream(&mut packet);
+        }
+
+        let length = u32::from_be_bytes([packet[0], packet[1], packet[2], packet[3]]) as usize;
+        if length + 4 < block || length > MAX_PACKET {
+            bail!("Client sent a packet with a bad length.");
+        }
+        let mut rest = vec![0; length + 4 - block];
+        self.stream
+            .read_exact(&mut rest)
+            .with_context(|| "Failed to read packet.")?;
+
+        if let Some(keys) = &mut self.recv.keys {
+            keys.cipher.apply_keystream(&mut rest);
+            packet.extend(rest);
+
+            let mut mac = [0; MAC_LEN];
+            self.stream
+                .read_exact(&mut mac)
+                .with_context(|| "Failed to read packet MAC.")?;
+            let mut expected = keys.mac.clone();
+            expected.update(&self.recv.seq.to_be_bytes());
+            expected.update(&packet);
+            expected
+                .verify_slice(&mac)
+                .map_err(|_| anyhow!("Client sent a packet with a bad MAC."))?;
+        } else {
+            packet.extend(rest);
+        }
+        self.recv.seq = self.recv.seq.wrapping_add(1);
+
+        let padding = packet[4] as usize;
+        if padding + 1 > length {
+            bail!("Client sent a packet with bad padding.");
+        }
+        Ok(packet[5..4 + length - padding].to_vec())
+    }
+
+    /// Sends `payload` in a packet.
+    pub(crate) fn write_packet(&mut self, payload: &[u8]) -> Result<()> {
+        let block = if self.send.keys.is_some() { 16 } else { 8 };
+        let mut padding = block - (payload.len() + 5) % block;
+        if padding < 4 {
+            padding += block;
+        }
+
+        let mut packet = Vec::with_capacity(payload.len() + padding + 5 + MAC_LEN);
+        packet.extend(((payload.len() + padding + 1) as u32).to_be_bytes());
+        packet.push(padding as u8);
+        packet.extend(payload);
+        packet.extend(std::iter::repeat_n(0, padding));
+
+        if let Some(keys) = &mut self.send.keys {
+            let mut mac = keys.mac.clone();
+            mac.update(&self.send.seq.to_be_bytes());
+            mac.update(&packet);
+            keys.cipher.apply_keystream(&mut packet);
+            packet.extend(mac.finalize().into_bytes());
+        }
+        self.send.seq = self.send.seq.wrapping_add(1);
+
+        self.stream
+            .write_all(&packet)
+            .with_context(|| "Failed to write packet.")
+    }
+}
+
+impl Keys {
+    /// Derives one direction's keys from the key exchange (RFC 4253 section
+    /// 7.2). This is the first key exchange, so the session ID is the
+    /// exchange hash.
+    fn derive(shared: &[u8], hash: &[u8], iv: u8, key: u8, mac: u8) -> Self {
+        let derive = |letter: u8| {
+            Sha256::new()
+                .chain_update(shared)
+                .chain_update(hash)
+                .chain_update([letter])
+                .chain_update(hash)
+                .finalize()
+        };
+
+        Self {
+            cipher: Ctr128BE::<Aes128>::new(derive(key)[..16].into(), derive(iv)[..16].into()),
+            mac: Hmac::new_from_slice(&derive(mac)).expect("HMAC accepts keys of any length"),
+        }
+    }
+}
+
+/// Builds our KEXINIT payload.
+/// Checks an ssh-ed25519 `signature` blob over `data` with the public key in
+/// `key_blob`.
+pub(crate) fn verify_signature(key_blob: &[u8], data: &[u8], signature: &[u8]) -> bool {
+    let verify = || -> Result<()> {
+        let mut key = Reader::new(key_blob);
+        let mut signature = Reader::new(signature);
+        if key.string()? != HOST_KEY_ALGORITHM.as_bytes()
+            || signature.string()? != HOST_KEY_ALGORITHM.as_bytes()
+        {
+            bail!("Not an ssh-ed25519 key or signature.");
+        }
+        let key = VerifyingKey::from_bytes(&key.string()?.try_into()?)?;
+        let signature = Signature::from_bytes(&signature.string()?.try_into()?);
+        Ok(key.verify(data, &signature)?)
+    };
+    verify().is_ok()
+}
+
+fn kexinit() -> Vec<u8> {
+    let mut cookie = [0; 16];
+    OsRng.fill_bytes(&mut cookie);
+
+    Writer::new()
+        .byte(MSG_KEXINIT)
+        .raw(&cookie)
+        .string(KEX_ALGORITHMS.as_bytes())
+        .string(HOST_KEY_ALGORITHM.as_bytes())
+        .string(CIPHER.as_bytes())
+        .string(CIPHER.as_bytes())
+        .string(MAC.as_bytes())
+        .string(MAC.as_bytes())
+        .string(COMPRESSION.as_bytes())
+        .string(COMPRESSION.as_bytes())
+        .string(b"")
+        .string(b"")
+        .byte(0)
+        .u32(0)
+        .finish()
+}
+
+/// Checks that the client supports the only algorithms we do.
+fn check_algorithms(kexinit: &[u8]) -> Result<()> {
+    let mut reader = Reader::new(kexinit);
+    reader.byte()?;
+    reader.raw(16)?;
+
+    let kex = reader.name_list()?;
+    if !KEX_ALGORITHMS
+        .split(',')
+        .any(|a| kex.iter().any(|k| k == a))
+    {
+        bail!("Client does not support curve25519-sha256 key exchange.");
+    }
+
+    for (wanted, what) in [
+        (HOST_KEY_ALGORITHM, "host key"),
+        (CIPHER, "cipher"),
+        (CIPHER, "cipher"),
+        (MAC, "MAC"),
+        (MAC, "MAC"),
+        (COMPRESSION, "compression"),
+        (COMPRESSION, "compression"),
+    ] {
+        if !reader.name_list()?.iter().any(|a| a == wanted) {
+            bail!("Client does not support {} {}.", what, wanted);
+        }
+    }
+
+    reader.name_list()?;
+    reader.name_list()?;
+    if reader.bool()? {
+        bail!("Client guessed a key exchange packet, which is not supported.");
+    }
+    Ok(())
+}
+
+/// Builds SSH wire-format data.
+pub(crate) struct Writer(Vec<u8>);
+
+impl Writer {
+    pub(crate) fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    pub(crate) fn byte(mut self, value: u8) -> Self {
+        self.0.push(value);
+        self
+    }
+
+    pub(crate) fn bool(self, value: bool) -> Self {
+        self.byte(value as u8)
+    }
+
+    pub(crate) fn u32(mut self, value: u32) -> Self {
+        self.0.extend(value.to_be_bytes());
+        self
+    }
+
+    pub(crate) fn raw(mut self, value: &[u8]) -> Self {
+        self.0.extend(value);
+        self
+    }
+
+    pub(crate) fn string(self, value: &[u8]) -> Self {
+        self.u32(value.len() as u32).raw(value)
+    }
+
+    /// Writes big-endian `value` as a multiple precision integer.
+    fn mpint(self, value: &[u8]) -> Self {
+        let value = &value[value.iter().take_while(|b| **b == 0).count()..];
+        if value.first().is_some_and(|b| b & 0x80 != 0) {
+            self.u32(value.len() as u32 + 1).byte(0).raw(value)
+        } else {
+            self.string(value)
+        }
+    }
+
+    pub(crate) fn finish(self) -> Vec<u8> {
+        self.0
+    }
+}
+
+/// Parses SSH wire-format data.
+pub(crate) struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self(data)
+    }
+
+    pub(crate) fn raw(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.0.len() < len {
+            bail!("Client sent a truncated message.");
+        }
+        let (value, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(value)
+    }
+
+    pub(crate) fn byte(&mut self) -> Result<u8> {
+        Ok(self.raw(1)?[0])
+    }
+
+    pub(crate) fn bool(&mut self) -> Result<bool> {
+        Ok(self.byte()? != 0)
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32> {
+        let bytes = self.raw(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub(crate) fn string(&mut self) -> Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.raw(len)
+    }
+
+    pub(crate) fn utf8(&mut self) -> Result<String> {
+        Ok(String::from_utf8_lossy(self.string()?).into_owned())
+    }
+
+    fn name_list(&mut self) -> Result<Vec<String>> {
+        Ok(self.utf8()?.split(',').map(String::from).collect())
+    }
+}
@@ -1,39 +1,150 @@
-use crate::Message;
+use crate::{
+    secret::Password,
+    session::OssiSession,
+    timeout::{as_millis, Deadline, TimeoutOverrides},
+    CancelToken, CommandSecret, ConnectFailure, EnvSecret, FileSecret, Logins, Message, OutputRows,
+    Phase, RunReport, SecretProvider, SessionPool, TimedOut, Timeouts,
+};
 use anyhow::{anyhow, Context, Result};
 use cached::{proc_macro::cached, Return, TimedCache};
-use ssh2::{KeyboardInteractivePrompt, Prompt, Session, Stream};
+use ssh2::{
+    Channel, CheckResult, HashType, KeyboardInteractivePrompt, KnownHostFileKind, Prompt, Session,
+    Stream,
+};
 use std::{
-    fmt::Debug,
+    error::Error,
+    fmt::{Debug, Display},
     io::{BufRead, BufReader, Read, Write},
-    net::{Ipv4Addr, TcpStream},
+    net::{IpAddr, Ipv6Addr, TcpStream, ToSocketAddrs},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
+const DEFAULT_KNOWN_HOSTS: &str = "./known_hosts";
 const DEFAULT_PORT: u16 = 5022;
-const OSSI_LOGOFF: &[u8] = b"clogoff\nt\ny\n";
+pub(crate) const OSSI_LOGOFF: &[u8] = b"clogoff\nt\ny\n";
 const OSSI_MAN_TERM: &[u8] = b"ossiem\n";
-const OSSI_TERM: &[u8] = b"ossie\n";
+pub(crate) const OSSI_TERM: &[u8] = b"ossie\n";
 const TERM: &str = "vt100";
 const TERM_DIMS: (u32, u32, u32, u32) = (81, 25, 0, 0);
-const TIMEOUT_MS: u32 = 30000; // Thirty second read/write timeout.
+
+/// Serializes known_hosts updates so parallel first-time connections don't
+/// overwrite each other's entries.
+static KNOWN_HOSTS_LOCK: Mutex<()> = Mutex::new(());
 
 /// Represents a Communication Manager and its login information. Executes
 /// collections of [Message]s on an ACM over SSH.
 #[derive(Clone)]
 pub struct Acm {
-    addr: Ipv4Addr,
+    host: String,
     port: Option<u16>,
     user: String,
-    pass: String,
+    pass: Password,
+    key: Option<SshKey>,
+    key_pass: Option<String>,
+    host_key_check: Option<HostKeyCheck>,
+    known_hosts: Option<PathBuf>,
+    timeouts: Option<Timeouts>,
+    timeout_overrides: TimeoutOverrides,
+    cancel: Option<CancelToken>,
+}
+
+/// A source of public-key credentials used to authenticate to an ACM. When an
+/// [Acm] has both a key and a password, the key is tried first and password
+/// authentication is used as a fallback.
+#[derive(Clone)]
+pub enum SshKey {
+    /// A private key file on disk (PEM or OpenSSH format).
+    File(PathBuf),
+    /// A private key held in memory (PEM or OpenSSH format).
+    Memory(String),
+    /// Every identity offered by a running ssh-agent (found via `SSH_AUTH_SOCK`).
+    Agent,
+}
+
+impl Debug for SshKey {
+    /// Formats a key source for debugging. Masks in-memory keys.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::File(path) => f.debug_tuple("File").field(path).finish(),
+            Self::Memory(_) => f.debug_tuple("Memory").field(&"********").finish(),
+            Self::Agent => f.write_str("Agent"),
+        }
+    }
 }
 
+/// How an ACM's SSH host key is verified against a known_hosts file before
+/// any credentials are sent to it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum HostKeyCheck {
+    /// Only connect to hosts whose key is already in known_hosts.
+    Strict,
+    /// Record unknown hosts in known_hosts on first connection, and only
+    /// connect to them afterwards if their key is unchanged.
+    #[default]
+    TrustOnFirstUse,
+    /// Do not verify host keys.
+    Disabled,
+}
+
+impl FromStr for HostKeyCheck {
+    type Err = anyhow::Error;
+
+    /// Parses `strict`, `tofu`, or `off` into a [HostKeyCheck].
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "strict" => Ok(Self::Strict),
+            "tofu" => Ok(Self::TrustOnFirstUse),
+            "off" => Ok(Self::Disabled),
+            _ => Err(anyhow!(
+                "Unknown host key check mode: {}. Expected strict, tofu, or off.",
+                s
+            )),
+        }
+    }
+}
+
+/// Returned (wrapped in an [anyhow::Error]) when an ACM presents a host key
+/// that differs from the one recorded in known_hosts. This may mean that the
+/// ACM was rebuilt, or that something is impersonating it. Callers can detect
+/// it with [anyhow::Error::downcast_ref].
+#[derive(Debug, Clone)]
+pub struct HostKeyMismatch {
+    pub host: String,
+    /// The SHA256 fingerprint of the new key, in unpadded base64 like
+    /// `ssh-keygen -l` prints it.
+    pub fingerprint: String,
+    pub known_hosts: PathBuf,
+}
+
+impl Display for HostKeyMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Host key for {} has changed (now SHA256:{}). Refusing to connect. If this change is expected, remove the old entry from {}.",
+            self.host,
+            self.fingerprint,
+            self.known_hosts.display()
+        )
+    }
+}
+
+impl Error for HostKeyMismatch {}
+
 impl Debug for Acm {
     /// Formats an ACM's configuration information for debugging. Masks passwords.
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Acm")
-            .field("addr", &self.addr)
+            .field("host", &self.host)
             .field("port", &self.port.unwrap_or(DEFAULT_PORT))
             .field("user", &self.user)
             .field("pass", &"********")
+            .field("key", &self.key)
+            .field("host_key_check", &self.host_key_check.unwrap_or_default())
+            .field("known_hosts", &self.known_hosts())
+            .field("timeouts", &self.timeouts())
             .finish()
     }
 }
@@ -41,18 +152,33 @@ impl Debug for Acm {
 impl Default for Acm {
     fn default() -> Self {
         Self {
-            addr: Ipv4Addr::new(127, 0, 0, 1),
+            host: String::from("127.0.0.1"),
             port: Default::default(),
             user: Default::default(),
             pass: Default::default(),
+            key: Default::default(),
+            key_pass: Default::default(),
+            host_key_check: Default::default(),
+            known_hosts: Default::default(),
+            timeouts: Default::default(),
+            timeout_overrides: Default::default(),
+            cancel: Default::default(),
         }
     }
 }
 
 impl Acm {
-    /// Adds an IPv4 address to ACM config.
-    pub fn with_addr(&mut self, addr: Ipv4Addr) -> &mut Self {
-        self.addr = addr;
+    /// Adds an IPv4 or IPv6 address to ACM config.
+    pub fn with_addr(&mut self, addr: impl Into<IpAddr>) -> &mut Self {
+        self.host = addr.into().to_string();
+        self
+    }
+
+    /// Adds a host to ACM config. This may be a DNS name or an IP address
+    /// (IPv6 addresses without brackets). DNS names are resolved at connect
+    /// time.
+    pub fn with_host(&mut self, host: &str) -> &mut Self {
+        self.host = host.into();
         self
     }
 
@@ -70,25 +196,335 @@ impl Acm {
 
     /// Adds a login password to ACM config.
     pub fn with_pass(&mut self, pass: &str) -> &mut Self {
-        self.pass = pass.into();
+        self.pass = Password::Plain(pass.into());
+        self
+    }
+
+    /// Looks up the login password from `provider` every time the ACM
+    /// connects, instead of keeping it in the ACM config. See [EnvSecret],
+    /// [FileSecret], and [CommandSecret].
+    pub fn with_pass_provider(&mut self, provider: impl SecretProvider + 'static) -> &mut Self {
+        self.pass = Password::Provider(Arc::new(provider));
+        self
+    }
+
+    /// Adds a private key file to ACM config for public-key authentication.
+    pub fn with_key_file(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        self.key = Some(SshKey::File(path.as_ref().into()));
+        self
+    }
+
+    /// Adds an in-memory private key to ACM config for public-key authentication.
+    pub fn with_key(&mut self, key: &str) -> &mut Self {
+        self.key = Some(SshKey::Memory(key.into()));
+        self
+    }
+
+    /// Configures the ACM to authenticate with identities from a running ssh-agent.
+    pub fn with_agent(&mut self) -> &mut Self {
+        self.key = Some(SshKey::Agent);
+        self
+    }
+
+    /// Adds a passphrase for decrypting the private key to ACM config.
+    pub fn with_key_pass(&mut self, key_pass: &str) -> &mut Self {
+        self.key_pass = Some(key_pass.into());
         self
     }
 
+    /// Sets how the ACM's host key is verified. Defaults to
+    /// [HostKeyCheck::TrustOnFirstUse].
+    pub fn with_host_key_check(&mut self, host_key_check: HostKeyCheck) -> &mut Self {
+        self.host_key_check = Some(host_key_check);
+        self
+    }
+
+    /// Sets the known_hosts file used for host key verification. Defaults to
+    /// `./known_hosts`.
+    pub fn with_known_hosts(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        self.known_hosts = Some(path.as_ref().into());
+        self
+    }
+
+    /// Like [Self::with_host_key_check] and [Self::with_known_hosts], but
+    /// only applies settings that were not already configured (e.g. by a
+    /// per-login option). Used for applying program-wide defaults.
+    pub fn with_host_key_defaults(
+        &mut self,
+        host_key_check: Option<HostKeyCheck>,
+        known_hosts: Option<&Path>,
+    ) -> &mut Self {
+        if self.host_key_check.is_none() {
+            self.host_key_check = host_key_check;
+        }
+        if self.known_hosts.is_none() {
+            self.known_hosts = known_hosts.map(PathBuf::from);
+        }
+        self
+    }
+
+    /// Sets time limits for connecting, logging in, each command, and the run
+    /// as a whole.
+    pub fn with_timeouts(&mut self, timeouts: Timeouts) -> &mut Self {
+        self.timeouts = Some(timeouts);
+        self
+    }
+
+    /// Like [Self::with_timeouts], but only applies if timeouts were not
+    /// already configured. Limits set by the login file are kept, and only
+    /// the rest are taken from `timeouts`. Used for applying program-wide
+    /// defaults.
+    pub fn with_timeout_defaults(&mut self, timeouts: Timeouts) -> &mut Self {
+        let overrides = self.timeout_overrides;
+        self.timeouts.get_or_insert_with(|| overrides.or(timeouts));
+        self
+    }
+
+    /// Sets the limits the login file gives this ACM. Any others come from
+    /// [Self::with_timeout_defaults], or the library defaults.
+    pub(crate) fn with_timeout_overrides(&mut self, overrides: TimeoutOverrides) -> &mut Self {
+        self.timeout_overrides = overrides;
+        self
+    }
+
+    /// Stops runs between commands once `cancel` is cancelled.
+    pub fn with_cancel(&mut self, cancel: CancelToken) -> &mut Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    pub(crate) fn timeouts(&self) -> Timeouts {
+        self.timeouts
+            .unwrap_or_else(|| self.timeout_overrides.or(Timeouts::default()))
+    }
+
+    pub(crate) fn cancel_token(&self) -> Option<&CancelToken> {
+        self.cancel.as_ref()
+    }
+
+    fn known_hosts(&self) -> &Path {
+        self.known_hosts
+            .as_deref()
+            .unwrap_or_else(|| Path::new(DEFAULT_KNOWN_HOSTS))
+    }
+
+    /// Verifies the host key presented during the SSH handshake against the
+    /// known_hosts file, according to the configured [HostKeyCheck].
+    fn verify_host_key(&self, session: &Session) -> Result<()> {
+        let host_key_check = self.host_key_check.unwrap_or_default();
+        if host_key_check == HostKeyCheck::Disabled {
+            return Ok(());
+        }
+
+        let port = self.port.unwrap_or(DEFAULT_PORT);
+        let host = self.host.clone();
+        let path = self.known_hosts();
+        let (key, key_type) = session
+            .host_key()
+            .ok_or_else(|| anyhow!("ACM did not present an SSH host key."))?;
+
+        let _lock = KNOWN_HOSTS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut known_hosts = session
+            .known_hosts()
+            .with_context(|| "Failed to initialize known hosts.")?;
+        if path.exists() {
+            known_hosts
+                .read_file(path, KnownHostFileKind::OpenSSH)
+                .with_context(|| format!("Failed to read known hosts file: {}", path.display()))?;
+        }
+
+        match known_hosts.check_port(&host, port, key) {
+            CheckResult::Match => Ok(()),
+            CheckResult::Mismatch => Err(HostKeyMismatch {
+                host,
+                fingerprint: session
+                    .host_key_hash(HashType::Sha256)
+                    .map(|hash| base64::encode_config(hash, base64::STANDARD_NO_PAD))
+                    .unwrap_or_default(),
+                known_hosts: path.into(),
+            }
+            .into()),
+            CheckResult::NotFound if host_key_check == HostKeyCheck::TrustOnFirstUse => {
+                let entry = if port == 22 {
+                    host
+                } else {
+                    format!("[{}]:{}", host, port)
+                };
+                known_hosts
+                    .add(&entry, key, &entry, key_type.into())
+                    .with_context(|| "Failed to add host key to known hosts.")?;
+                known_hosts
+                    .write_file(path, KnownHostFileKind::OpenSSH)
+                    .with_context(|| {
+                        format!("Failed to write known hosts file: {}", path.display())
+                    })
+            }
+            CheckResult::NotFound => Err(anyhow!(
+                "Host key for {} is not in {} and strict host key checking is enabled.",
+                host,
+                path.display()
+            )),
+            CheckResult::Failure => Err(anyhow!("Failed to check host key for {}.", host)),
+        }
+    }
+
+    /// Authenticates an SSH session with the configured key (if any), falling
+    /// back to keyboard-interactive password authentication when a password
+    /// is configured.
+    fn authenticate(&self, session: &Session) -> Result<()> {
+        if let Some(key) = &self.key {
+            let key_pass = self.key_pass.as_deref();
+            let result = match key {
+                SshKey::File(path) => session
+                    .userauth_pubkey_file(&self.user, None, path, key_pass)
+                    .with_context(|| "Public key file SSH authentication failed."),
+                SshKey::Memory(key) => session
+                    .userauth_pubkey_memory(&self.user, None, key, key_pass)
+                    .with_context(|| "In-memory public key SSH authentication failed."),
+                SshKey::Agent => self.authenticate_agent(session),
+            };
+
+            if result.is_ok() || !self.pass.is_set() {
+                return result;
+            }
+        }
+
+        let pass = self
+            .pass
+            .resolve()
+            .with_context(|| "Failed to look up ACM password.")?;
+        session
+            .userauth_keyboard_interactive(&self.user, &mut SshPrompter::new(&pass))
+            .with_context(|| "Interactive SSH keyboard authentication failed.")
+    }
+
+    /// Tries every identity offered by ssh-agent until one is accepted.
+    fn authenticate_agent(&self, session: &Session) -> Result<()> {
+        let mut agent = session
+            .agent()
+            .with_context(|| "Failed to initialize ssh-agent.")?;
+        agent
+            .connect()
+            .with_context(|| "Failed to connect to ssh-agent. Make sure SSH_AUTH_SOCK is set.")?;
+        agent
+            .list_identities()
+            .with_context(|| "Failed to list ssh-agent identities.")?;
+
+        let accepted = agent
+            .identities()
+            .with_context(|| "Failed to read ssh-agent identities.")?
+            .iter()
+            .any(|identity| agent.userauth(&self.user, identity).is_ok());
+        let _ = agent.disconnect();
+
+        if accepted {
+            Ok(())
+        } else {
+            Err(anyhow!("No ssh-agent identity was accepted."))
+        }
+    }
+
+    /// Resolves the ACM's host and opens a TCP stream to the first of its
+    /// addresses that accepts a connection within the connect timeout.
+    fn connect(&self, deadline: &Deadline) -> Result<TcpStream> {
+        let addrs = (self.host.as_str(), self.port.unwrap_or(DEFAULT_PORT))
+            .to_socket_addrs()
+            .with_context(|| format!("Failed to resolve ACM host: {}", self.host))?;
+
+        let mut error = anyhow!("ACM host resolved to no addresses: {}", self.host);
+        for addr in addrs {
+            let (limit, timeout) = deadline.limit(Phase::Connect, self.timeouts().connect)?;
+            match timeout.attach(TcpStream::connect_timeout(&addr, limit).map_err(Into::into)) {
+                Ok(stream) => return Ok(stream),
+                Err(e) => error = e.context(format!("Failed to connect to {}", addr)),
+            }
+        }
+
+        Err(error.context(ConnectFailure::Connect))
+    }
+
+    /// Identifies the ACM itself (host and port), regardless of which user
+    /// logs in. Used for per-ACM concurrency limits.
+    pub(crate) fn addr_key(&self) -> String {
+        format!("{}:{}", self.host, self.port.unwrap_or(DEFAULT_PORT))
+    }
+
+    /// Identifies the login (user, host, and port) this ACM connects with.
+    /// Used for keying pooled sessions.
+    pub(crate) fn login_key(&self) -> String {
+        format!(
+            "{}@{}:{}",
+            self.user,
+            self.host,
+            self.port.unwrap_or(DEFAULT_PORT)
+        )
+    }
+
     /// Opens a readable and writable SSH stream into an ACM's Site Administration Terminal.
     fn open_stream(&self, term: &[u8]) -> Result<Stream> {
+        CancelToken::check(self.cancel_token())?;
+        let deadline = Deadline::new(&self.timeouts());
+        let (session, channel) = self.open_channel(term, &deadline, &mut RunReport::default())?;
+        let (limit, _) = deadline.limit(Phase::Command, self.timeouts().command)?;
+        session.set_timeout(as_millis(limit));
+        Ok(channel.stream(0))
+    }
+
+    /// Logs into the ACM's Site Administration Terminal and opens `term` on a
+    /// new SSH channel. Returns the session and channel once the OSSI
+    /// terminator has been read. Fails if this takes longer than the login
+    /// timeout. Records how long connecting and logging in took in `report`.
+    pub(crate) fn open_channel(
+        &self,
+        term: &[u8],
+        deadline: &Deadline,
+        report: &mut RunReport,
+    ) -> Result<(Session, Channel)> {
+        let started = Instant::now();
+        let stream = self.connect(deadline)?;
+        report.connect = Some(started.elapsed());
+
+        let started = Instant::now();
+        let (limit, timeout) = deadline.limit(Phase::Login, self.timeouts().login)?;
+        let opened = timeout.attach(self.login(stream, term, limit, timeout));
+        report.login = Some(started.elapsed());
+        opened
+    }
+
+    /// Logs into SAT over `stream` and opens `term`, failing with `timeout` if
+    /// the OSSI terminator isn't reached within `limit`.
+    fn login(
+        &self,
+        stream: TcpStream,
+        term: &[u8],
+        limit: Duration,
+        timeout: TimedOut,
+    ) -> Result<(Session, Channel)> {
+        let started = Instant::now();
+
         // Initialize SSH session.
-        let stream = TcpStream::connect((self.addr, self.port.unwrap_or(DEFAULT_PORT)))
-            .with_context(|| "Failed to open TCP stream to host. Make sure the config is correct and the host is otherwise reachable.")?;
         let mut session = Session::new().with_context(|| "Failed to start SSH session.")?;
         session.set_tcp_stream(stream);
-        session.set_timeout(TIMEOUT_MS);
-        session
-            .handshake()
-            .with_context(|| "SSH handshake failed.")?;
-        session
-            .userauth_keyboard_interactive(&self.user, &mut SshPrompter::new(&self.pass))
-            .with_context(|| "Interactive SSH keyboard authentication failed.")?;
+        session.set_timeout(as_millis(limit));
+        session.handshake().context(ConnectFailure::Handshake)?;
+        self.verify_host_key(&session)?;
+        self.authenticate(&session)?;
 
+        let channel = self
+            .open_term(&session, term, started, limit, timeout)
+            .context(ConnectFailure::Prompt)?;
+        Ok((session, channel))
+    }
+
+    /// Opens `term` in a shell on a new channel of a logged-in `session`.
+    fn open_term(
+        &self,
+        session: &Session,
+        term: &[u8],
+        started: Instant,
+        limit: Duration,
+        timeout: TimedOut,
+    ) -> Result<Channel> {
         // Open shell on SSH channel.
         let mut channel = session
             .channel_session()
@@ -108,27 +544,76 @@ impl Acm {
         let mut lines = BufReader::new(channel.stream(0)).lines();
         while let Some(Ok(line)) = lines.next() {
             if line == "t" {
-                return Ok(channel.stream(0));
+                return Ok(channel);
             }
         }
 
-        Err(anyhow!("Never reached OSSI term prompt."))
+        if started.elapsed() >= limit {
+            Err(anyhow!(timeout))
+        } else {
+            Err(anyhow!(
+                "SAT closed the session or sent no OSSI terminator."
+            ))
+        }
     }
 
     /// Runs a given collection of [Message]s (OSSI commands) on the ACM and returns their resulting output.
+    /// Each command is sent once the previous one has finished, so the run
+    /// can be cancelled between commands and each command is held to the
+    /// command timeout.
     pub fn run(&self, inputs: &[Message]) -> Result<Vec<Message>> {
+        self.run_report(inputs).into_outputs()
+    }
+
+    /// Like [Self::run], but returns a [RunReport] pairing each input with its
+    /// output and recording how long each step took.
+    pub fn run_report(&self, inputs: &[Message]) -> RunReport {
+        let started = Instant::now();
+        let mut report = RunReport::new(inputs);
+        report.attempts = 1;
+        report.error = self.run_into(&mut report).err();
+        report.elapsed = started.elapsed();
+        report
+    }
+
+    /// Runs the inputs of `report` on a new login, recording the outputs and
+    /// timings in it.
+    pub(crate) fn run_into(&self, report: &mut RunReport) -> Result<()> {
+        CancelToken::check(self.cancel_token())?;
+        let deadline = Deadline::new(&self.timeouts());
+        let mut session = OssiSession::open(self, &deadline, report)?;
+        session.run(
+            report,
+            self.timeouts().command,
+            &deadline,
+            self.cancel_token(),
+        )
+    }
+
+    /// Like [Self::run], but returns the output as a stream of rows that are
+    /// parsed as they arrive from the ACM, instead of waiting for all of the
+    /// [Message]s to finish. Use [OutputRows::messages] to stream whole
+    /// [Message]s instead. Streams always use a new login. All inputs are sent
+    /// at once, so the command timeout applies to each read from the stream.
+    pub fn run_stream(&self, inputs: &[Message]) -> Result<OutputRows<BufReader<Stream>>> {
+        Ok(Message::stream_rows(self.send_inputs(inputs)?))
+    }
+
+    /// Logs in and sends `inputs` followed by a LOGOFF, returning the stream
+    /// to read their outputs from.
+    pub(crate) fn send_inputs(&self, inputs: &[Message]) -> Result<Stream> {
         let inputs: String = inputs.iter().map(Message::to_string).collect();
         let mut stream = self.open_stream(OSSI_TERM)?;
         write!(stream, "{}", inputs).with_context(|| "Failed to write inputs to OSSI stream.")?;
         stream
             .write_all(OSSI_LOGOFF)
             .with_context(|| "Failed to write LOGOFF to OSSI stream.")?;
-        Message::from_output(stream)
+        Ok(stream)
     }
 
     /// Like [Self::run], but caches results with a timed cache of thirty minutes.
     pub fn run_cached(&self, inputs: &[Message]) -> Result<Vec<Message>> {
-        Ok(run_cached(self, inputs)?.value)
+        Ok(run_cached(self, None, inputs)?.value)
     }
 
     /// Like [Self::run], but instead of running [Message]s, it returns the manual pages for the provided OSSI commands.
@@ -151,60 +636,199 @@ impl Acm {
     /// names/labels. The spec looks like this:
     ///
     /// ```text
-    /// (name user:pass@addr:optional_port)
+    /// (name user:pass@host:optional_port optional_options)
     /// ACM01 admin:secret@192.168.1.1:5022
     /// ACM02 acdmin:secret@192.168.1.2
     /// ACM03 acdmin:secret@192.168.1.3:5023
+    /// ACM03B acdmin:secret@cm03b.example.com:5023
+    /// ACM03C acdmin:secret@[2001:db8::3]:5023
+    /// ACM04 admin@192.168.1.4 key=/home/admin/.ssh/id_rsa key-pass=secret
+    /// ACM05 admin:secret@192.168.1.5 agent
+    /// ACM06 admin:secret@192.168.1.6 hostkey=strict known-hosts=/etc/angelshark/known_hosts
+    /// ACM07 admin@192.168.1.7 pass-file=/run/secrets/acm07
     /// ```
     ///
-    /// The port is optional. If it is not provided, the default SAT port of
-    /// 5022 will be used.
+    /// The host may be a DNS name, an IPv4 address, or an IPv6 address. IPv6
+    /// addresses must be enclosed in brackets when followed by a port. The port
+    /// is optional. If it is not provided, the default SAT port of 5022 will be
+    /// used.
+    ///
+    /// The password is optional when a key is configured. A `key=path` option
+    /// authenticates with a private key file (decrypted with an optional
+    /// `key-pass=passphrase`) and an `agent` option authenticates with
+    /// ssh-agent. If a password is also given, it is used as a fallback when
+    /// key authentication fails.
+    ///
+    /// Instead of being written in the line, the password can be looked up
+    /// every time the ACM connects with a `pass-env=VARIABLE`,
+    /// `pass-file=path`, or `pass-cmd=command` option. See [SecretProvider].
+    ///
+    /// A `hostkey=strict|tofu|off` option sets how the ACM's host key is
+    /// verified (see [HostKeyCheck]), and a `known-hosts=path` option sets the
+    /// known_hosts file it is verified against.
+    ///
+    /// Blank lines and lines starting with `#` are ignored. Lines starting
+    /// with `@` define groups of ACM names, which are checked but not
+    /// returned here. Use [crate::Logins] to read them too. Malformed lines
+    /// and duplicate ACM names are errors naming their line.
     pub fn from_logins(readable: impl Read) -> Result<Vec<(String, Self)>> {
-        let mut acms = Vec::new();
-
-        for line in BufReader::new(readable).lines() {
-            let line = line.with_context(|| "Failed to read line of config.")?;
-            let mut acm = Self::default();
-
-            if let Some((name, config)) = line.split_once(' ') {
-                if let Some((creds, dest)) = config.split_once('@') {
-                    if let Some((user, pass)) = creds.split_once(':') {
-                        if let Some((addr, port)) = dest.split_once(':') {
-                            acm.with_port(
-                                port.parse()
-                                    .with_context(|| "Failed to parse ACM socket port.")?,
-                            )
-                            .with_addr(
-                                addr.parse()
-                                    .with_context(|| "Failed to parse ACM IP address.")?,
-                            );
-                        } else {
-                            acm.with_addr(
-                                dest.parse()
-                                    .with_context(|| "Failed to parse ACM IP address.")?,
-                            );
-                        }
-
-                        acm.with_user(user).with_pass(pass);
-                        acms.push((name.into(), acm));
-                    }
+        Ok(Logins::from_legacy(readable)?.acms)
+    }
+
+    /// Parses one `asa.cfg` line as a named ACM login. Returns `None` for
+    /// blank, comment, and group lines.
+    pub(crate) fn from_login_line(line: &str) -> Result<Option<(String, Self)>> {
+        let mut acm = Self::default();
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with('@') {
+            return Ok(None);
+        }
+
+        let (name, config) = line
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| anyhow!("Missing login after ACM name: {}", line))?;
+        let (creds, dest) = config
+            .trim_start()
+            .split_once('@')
+            .ok_or_else(|| anyhow!("Missing '@' between credentials and address for {}", name))?;
+        let dest = dest.trim();
+        let (dest, options) = dest.split_once(char::is_whitespace).unwrap_or((dest, ""));
+        let options = split_options(options)
+            .ok_or_else(|| anyhow!("Missing closing quote in login options for {}", name))?;
+
+        let (host, port) = split_host_port(dest)?;
+        acm.with_host(host);
+        if let Some(port) = port {
+            acm.with_port(port);
+        }
+
+        let (user, pass) = match creds.split_once(':') {
+            Some((user, pass)) => (user, Some(pass)),
+            None => (creds, None),
+        };
+        if user.is_empty() {
+            return Err(anyhow!("Missing username for {}", name));
+        }
+        acm.with_user(user);
+        if let Some(pass) = pass {
+            acm.with_pass(pass);
+        }
+
+        let mut passwords = usize::from(pass.is_some());
+        for option in &options {
+            match option.split_once('=') {
+                Some(("pass-env", var)) => {
+                    passwords += 1;
+                    acm.with_pass_provider(EnvSecret { name: var.into() })
+                }
+                Some(("pass-file", path)) => {
+                    passwords += 1;
+                    acm.with_pass_provider(FileSecret { path: path.into() })
                 }
+                Some(("pass-cmd", command)) => {
+                    passwords += 1;
+                    acm.with_pass_provider(CommandSecret {
+                        command: command.into(),
+                    })
+                }
+                Some(("key", path)) => acm.with_key_file(path),
+                Some(("key-pass", key_pass)) => acm.with_key_pass(key_pass),
+                Some(("hostkey", mode)) => acm.with_host_key_check(mode.parse()?),
+                Some(("known-hosts", path)) => acm.with_known_hosts(path),
+                None if option == "agent" => acm.with_agent(),
+                None => {
+                    return Err(anyhow!(
+                        "Unknown login option for {}: {} (quote option values with spaces, ex. pass-cmd=\"pass show {}\")",
+                        name,
+                        option,
+                        name
+                    ))
+                }
+                _ => return Err(anyhow!("Unknown login option for {}: {}", name, option)),
+            };
+        }
+        if passwords > 1 {
+            return Err(anyhow!("More than one password for {}", name));
+        }
+
+        Ok(Some((name.into(), acm)))
+    }
+}
+
+/// Splits `asa.cfg` login options on whitespace. Double or single quotes keep
+/// spaces in an option (ex. `pass-cmd="pass show cm01"`). Returns [None] if a
+/// quote is not closed.
+fn split_options(options: &str) -> Option<Vec<String>> {
+    let mut split = Vec::new();
+    let mut option: Option<String> = None;
+    let mut quote = None;
+    for c in options.chars() {
+        match quote {
+            Some(open) if c == open => quote = None,
+            None if c == '"' || c == '\'' => {
+                quote = Some(c);
+                option.get_or_insert_with(String::new);
             }
+            None if c.is_whitespace() => split.extend(option.take()),
+            _ => option.get_or_insert_with(String::new).push(c),
+        }
+    }
+    split.extend(option);
+    quote.is_none().then_some(split)
+}
+
+/// Splits an `asa.cfg` login destination into its host and optional port.
+/// Accepts `host`, `host:port`, `ipv6`, `[ipv6]`, and `[ipv6]:port`.
+fn split_host_port(dest: &str) -> Result<(&str, Option<u16>)> {
+    let (host, port) = if let Some(bracketed) = dest.strip_prefix('[') {
+        let (host, rest) = bracketed
+            .split_once(']')
+            .ok_or_else(|| anyhow!("Missing closing bracket in ACM address: {}", dest))?;
+        match rest.strip_prefix(':') {
+            Some(port) => (host, Some(port)),
+            None if rest.is_empty() => (host, None),
+            None => return Err(anyhow!("Unexpected text after ACM address: {}", dest)),
         }
-        Ok(acms)
+    } else if dest.parse::<Ipv6Addr>().is_ok() {
+        (dest, None)
+    } else if let Some((host, port)) = dest.split_once(':') {
+        (host, Some(port))
+    } else {
+        (dest, None)
+    };
+
+    if host.is_empty() {
+        return Err(anyhow!("Missing ACM host in login: {}", dest));
     }
+
+    let port = port
+        .map(|port| port.parse())
+        .transpose()
+        .with_context(|| "Failed to parse ACM socket port.")?;
+    Ok((host, port))
 }
 
 /// This memoized function is a timed cache where entries are evicted after
-/// thirty minutes. Errors are not cached, only successes.
+/// thirty minutes. Errors are not cached, only successes. Entries are keyed
+/// by login, so users never see each other's cached output.
+/// When a [SessionPool] is given, uncached runs are executed on a pooled
+/// session instead of a new login.
 #[cached(
     result = true,
     type = "TimedCache<String, Return<Vec<Message>>>",
     create = "{ TimedCache::with_lifespan(1800) }",
-    convert = r#"{ format!("{}{:?}{:?}", acm.addr, acm.port, inputs) }"#
+    convert = r#"{ format!("{}{:?}", acm.login_key(), inputs) }"#
 )]
-fn run_cached(acm: &Acm, inputs: &[Message]) -> Result<Return<Vec<Message>>> {
-    Ok(Return::new(acm.run(inputs)?))
+pub(crate) fn run_cached(
+    acm: &Acm,
+    pool: Option<&SessionPool>,
+    inputs: &[Message],
+) -> Result<Return<Vec<Message>>> {
+    let outputs = match pool {
+        Some(pool) => pool.run(acm, inputs)?,
+        None => acm.run(inputs)?,
+    };
+    Ok(Return::new(outputs))
 }
 
 /// Used internally for password-based SSH authentication.
@@ -228,3 +852,78 @@ impl<'a> KeyboardInteractivePrompt for SshPrompter<'a> {
         vec![self.pass.to_owned()]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{split_host_port, Acm, SshKey};
+
+    #[test]
+    fn login_options_may_be_quoted() {
+        let line = r#"CM01 mock@192.0.2.10 pass-cmd="printf 'secret word'" key='/keys/cm 01'"#;
+        let (name, acm) = Acm::from_login_line(line).unwrap().unwrap();
+
+        assert_eq!(name, "CM01");
+        assert_eq!(acm.pass.resolve().unwrap(), "secret word");
+        assert!(
+            matches!(acm.key, Some(SshKey::File(path)) if path.to_str() == Some("/keys/cm 01"))
+        );
+    }
+
+    #[test]
+    fn bad_login_options_are_rejected() {
+        let cases = [
+            (
+                r#"CM01 mock@192.0.2.10 pass-cmd="pass show cm01"#,
+                "Missing closing quote in login options for CM01",
+            ),
+            (
+                "CM01 mock@192.0.2.10 pass-cmd=pass show cm01",
+                "Unknown login option for CM01: show (quote option values with spaces",
+            ),
+            (
+                "CM01 mock@192.0.2.10 passcmd=pass",
+                "Unknown login option for CM01: passcmd=pass",
+            ),
+        ];
+
+        for (line, expected) in cases {
+            let error = Acm::from_login_line(line).err().unwrap().to_string();
+            assert!(error.starts_with(expected), "{}: {}", line, error);
+        }
+    }
+
+    #[test]
+    fn split_host_port_accepts_names_and_addresses() {
+        let cases = [
+            ("cm01.example.com", ("cm01.example.com", None)),
+            ("cm01.example.com:5022", ("cm01.example.com", Some(5022))),
+            ("192.0.2.10", ("192.0.2.10", None)),
+            ("192.0.2.10:5022", ("192.0.2.10", Some(5022))),
+            ("2001:db8::10", ("2001:db8::10", None)),
+            ("[2001:db8::10]", ("2001:db8::10", None)),
+            ("[2001:db8::10]:5022", ("2001:db8::10", Some(5022))),
+        ];
+
+        for (dest, expected) in cases {
+            assert_eq!(split_host_port(dest).unwrap(), expected, "{}", dest);
+        }
+    }
+
+    #[test]
+    fn split_host_port_rejects_bad_destinations() {
+        let cases = [
+            ("cm01.example.com:ssh", "Failed to parse ACM socket port."),
+            ("cm01.example.com:65536", "Failed to parse ACM socket port."),
+            ("[2001:db8::10]:", "Failed to parse ACM socket port."),
+            ("[2001:db8::10", "Missing closing bracket in ACM address"),
+            ("[2001:db8::10]5022", "Unexpected text after ACM address"),
+            (":5022", "Missing ACM host in login"),
+            ("[]:5022", "Missing ACM host in login"),
+        ];
+
+        for (dest, expected) in cases {
+            let error = split_host_port(dest).unwrap_err().to_string();
+            assert!(error.starts_with(expected), "{}: {}", dest, error);
+        }
+    }
+}
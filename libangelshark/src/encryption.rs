@@ -0,0 +1,74 @@
+use crate::Logins;
+use anyhow::{anyhow, Result};
+use argon2::Argon2;
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng, Payload},
+    Key, XChaCha20Poly1305, XNonce,
+};
+
+/// Starts every encrypted logins file, so they can be told apart from
+/// plaintext ones.
+const MAGIC: &[u8] = b"angelshark encrypted logins v1\n";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+
+impl Logins {
+    /// Whether `contents` is a logins file encrypted with [Self::encrypt].
+    pub fn is_encrypted(contents: &[u8]) -> bool {
+        contents.starts_with(MAGIC)
+    }
+
+    /// Encrypts the contents of a logins file with `passphrase`. The key is
+    /// derived with Argon2id and a random salt, and the contents are sealed
+    /// with XChaCha20-Poly1305, so any tampering is caught on decryption.
+    pub fn encrypt(contents: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+        let mut salt = [0; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let sealed = cipher(passphrase, &salt)?
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: contents,
+                    aad: MAGIC,
+                },
+            )
+            .map_err(|_| anyhow!("Failed to encrypt logins."))?;
+
+        Ok([MAGIC, &salt, &nonce, &sealed].concat())
+    }
+
+    /// Decrypts a logins file encrypted with [Self::encrypt]. Fails if
+    /// `passphrase` is wrong or the file was changed.
+    pub fn decrypt(encrypted: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+        let body = encrypted
+            .strip_prefix(MAGIC)
+            .ok_or_else(|| anyhow!("Logins are not encrypted."))?;
+        if body.len() < SALT_LEN + NONCE_LEN {
+            return Err(anyhow!("Encrypted logins are truncated."));
+        }
+        let (salt, body) = body.split_at(SALT_LEN);
+        let (nonce, sealed) = body.split_at(NONCE_LEN);
+
+        cipher(passphrase, salt)?
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: sealed,
+                    aad: MAGIC,
+                },
+            )
+            .map_err(|_| {
+                anyhow!("Failed to decrypt logins. The key is wrong or the file is damaged.")
+            })
+    }
+}
+
+/// Derives the file key for `passphrase` and `salt`.
+fn cipher(passphrase: &str, salt: &[u8]) -> Result<XChaCha20Poly1305> {
+    let mut key = [0; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow!("Failed to derive logins key: {}", e))?;
+    Ok(XChaCha20Poly1305::new(Key::from_slice(&key)))
+}
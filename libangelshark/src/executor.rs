@@ -0,0 +1,173 @@
+use crate::{timeout::CANCEL_POLL, Acm, CancelToken};
+use anyhow::{Context, Result};
+use rayon::{ThreadPool, ThreadPoolBuilder, Yield};
+use ssh2::Stream;
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    io::{self, Read},
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+};
+
+/// Runs [crate::AcmRunner] jobs on a dedicated thread pool instead of rayon's
+/// global one, so that runners sharing an executor queue behind each other
+/// rather than starving other parallel work. Clones share the same threads
+/// and limits.
+///
+/// At most `max_parallel` jobs run at a time overall, and at most
+/// `max_per_acm` of them log in to the same ACM (host and port) at a time.
+/// Jobs over either limit wait for a slot. Jobs waiting for their ACM do so
+/// before taking one of the executor's threads, so they never hold up jobs
+/// for other ACMs. Time spent waiting does not count against the run timeout.
+#[derive(Clone)]
+pub struct Executor {
+    inner: Arc<ExecutorInner>,
+}
+
+struct ExecutorInner {
+    threads: ThreadPool,
+    running: Mutex<HashMap<String, usize>>,
+    released: Condvar,
+    max_parallel: usize,
+    max_per_acm: usize,
+}
+
+impl Debug for Executor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Executor")
+            .field("max_parallel", &self.inner.max_parallel)
+            .field("max_per_acm", &self.inner.max_per_acm)
+            .finish()
+    }
+}
+
+impl Executor {
+    /// Starts `max_parallel` threads for running jobs, allowing up to
+    /// `max_per_acm` of them on the same ACM at a time.
+    pub fn new(max_parallel: usize, max_per_acm: usize) -> Result<Self> {
+        let max_parallel = max_parallel.max(1);
+        let threads = ThreadPoolBuilder::new()
+            .num_threads(max_parallel)
+            .thread_name(|index| format!("angelshark-{}", index))
+            .build()
+            .with_context(|| "Failed to start executor threads.")?;
+
+        Ok(Self {
+            inner: Arc::new(ExecutorInner {
+                threads,
+                running: Mutex::new(HashMap::new()),
+                released: Condvar::new(),
+                max_parallel,
+                max_per_acm: max_per_acm.max(1),
+            }),
+        })
+    }
+
+    /// Calls `run` on one of the executor's threads once `acm` is below its
+    /// limit, holding the ACM's slot until `run` returns.
+    pub(crate) fn run<T: Send>(
+        &self,
+        acm: &Acm,
+        run: impl FnOnce() -> Result<T> + Send,
+    ) -> Result<T> {
+        // The slot is released on the executor's thread, since this one may
+        // be running other jobs (that wait for the same slot) by then.
+        let slot = self.acquire(acm)?;
+        self.inner.threads.install(move || {
+            let _slot = slot;
+            run()
+        })
+    }
+
+    /// Like [Self::run], but keeps the ACM's slot until the returned stream is
+    /// dropped.
+    pub(crate) fn open(
+        &self,
+        acm: &Acm,
+        open: impl FnOnce() -> Result<Stream> + Send,
+    ) -> Result<RunnerStream> {
+        let slot = self.acquire(acm)?;
+        Ok(RunnerStream {
+            stream: self.inner.threads.install(open)?,
+            _slot: Some(slot),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, usize>> {
+        self.inner.running.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Waits for a slot on `acm`, giving up if its run is cancelled. If
+    /// called from a rayon thread (such as one running an [crate::AcmRunner]'s
+    /// jobs), runs that thread's other jobs while it waits, so that jobs for
+    /// other ACMs are still dispatched.
+    fn acquire(&self, acm: &Acm) -> Result<Slot> {
+        let key = acm.addr_key();
+
+        loop {
+            CancelToken::check(acm.cancel_token())?;
+            let mut running = self.lock();
+            let count = running.entry(key.clone()).or_default();
+            if *count < self.inner.max_per_acm {
+                *count += 1;
+                return Ok(Slot {
+                    inner: self.inner.clone(),
+                    key,
+                });
+            }
+
+            drop(running);
+            if rayon::yield_now() == Some(Yield::Executed) {
+                continue;
+            }
+            let running = self.lock();
+            let _running = self
+                .inner
+                .released
+                .wait_timeout(running, CANCEL_POLL)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+}
+
+/// One running job's claim on its ACM. Released when dropped.
+struct Slot {
+    inner: Arc<ExecutorInner>,
+    key: String,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let mut running = self.inner.running.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(count) = running.get_mut(&self.key) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                running.remove(&self.key);
+            }
+        }
+        self.inner.released.notify_all();
+    }
+}
+
+/// An OSSI output stream from [crate::AcmRunner::run_stream]. When the runner
+/// has an [Executor], the stream counts against its ACM's limit until it is
+/// dropped.
+pub struct RunnerStream {
+    stream: Stream,
+    _slot: Option<Slot>,
+}
+
+impl RunnerStream {
+    pub(crate) fn new(stream: Stream) -> Self {
+        Self {
+            stream,
+            _slot: None,
+        }
+    }
+}
+
+impl Read for RunnerStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
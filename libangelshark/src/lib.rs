@@ -1,7 +1,27 @@
 mod acm;
+mod encryption;
+mod executor;
+mod logins;
 mod message;
+#[cfg(feature = "tokio")]
+mod nonblocking;
+mod plan;
+mod pool;
+mod report;
+mod retry;
 mod runner;
+mod secret;
+mod session;
+mod timeout;
 
 pub use acm::*;
+pub use executor::*;
+pub use logins::*;
 pub use message::*;
+pub use plan::*;
+pub use pool::*;
+pub use report::*;
+pub use retry::*;
 pub use runner::*;
+pub use secret::*;
+pub use timeout::*;
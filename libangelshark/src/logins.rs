@@ -0,0 +1,380 @@
+use crate::{timeout::TimeoutOverrides, Acm, CommandSecret, EnvSecret, FileSecret, SecretProvider};
+use anyhow::{anyhow, Context, Result};
+use glob::Pattern;
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{BufRead, BufReader, Read},
+    path::PathBuf,
+    time::Duration,
+};
+use toml::Spanned;
+
+/// The ACM logins and groups read from a logins file, either in the legacy
+/// `asa.cfg` format or in TOML.
+///
+/// Legacy logins are parsed as in [Acm::from_logins]. Groups give a name to
+/// several ACMs at once:
+///
+/// ```text
+/// @east = ACM01 ACM02 ACM03
+/// @lab = LAB*
+/// ```
+///
+/// Members may be ACM names or glob patterns. Register groups on an
+/// [crate::AcmRunner] with [crate::AcmRunner::register_group], then queue
+/// inputs for `@east` to run them on every member.
+///
+/// See [Self::from_toml] for the TOML format.
+#[derive(Debug, Default, Clone)]
+pub struct Logins {
+    pub acms: Vec<(String, Acm)>,
+    pub groups: Vec<(String, Vec<String>)>,
+    /// Descriptions and tags of ACMs that have any, from TOML logins.
+    pub details: Vec<(String, AcmDetails)>,
+}
+
+/// Information about an ACM that is not needed to log in to it.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct AcmDetails {
+    pub description: Option<String>,
+    /// Every tag is also a group containing the ACM.
+    pub tags: Vec<String>,
+}
+
+impl Logins {
+    /// Reads from `readable`, parsing it as TOML if its first line that isn't
+    /// blank or a comment is a table header (ex. `[acms.CM01]`), and as the
+    /// legacy `asa.cfg` format otherwise. Fails on encrypted logins. Use
+    /// [Self::from_reader_with_key] for those.
+    pub fn from_reader(readable: impl Read) -> Result<Self> {
+        Self::from_reader_with_key(readable, None)
+    }
+
+    /// Like [Self::from_reader], but first decrypts logins encrypted with
+    /// [Self::encrypt], using the passphrase from `key`. Plaintext logins are
+    /// read as they are.
+    pub fn from_reader_with_key(
+        mut readable: impl Read,
+        key: Option<&dyn SecretProvider>,
+    ) -> Result<Self> {
+        let mut contents = Vec::new();
+        readable
+            .read_to_end(&mut contents)
+            .with_context(|| "Failed to read logins.")?;
+        if Self::is_encrypted(&contents) {
+            let key = key.ok_or_else(|| anyhow!("Logins are encrypted, but no key was given."))?;
+            contents = Self::decrypt(&contents, &key.secret()?)?;
+        }
+        let source = String::from_utf8(contents).with_context(|| "Logins are not valid UTF-8.")?;
+
+        let first = source
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with('#'));
+        if first.is_some_and(|line| line.starts_with('[')) {
+            Self::from_toml(&source)
+        } else {
+            Self::from_legacy(source.as_bytes())
+        }
+    }
+
+    /// Reads from `readable`, parsing lines as legacy `asa.cfg` logins and
+    /// groups. Blank lines and lines starting with `#` are ignored. Malformed
+    /// lines and names defined more than once are errors naming their line.
+    pub fn from_legacy(readable: impl Read) -> Result<Self> {
+        let mut logins = Self::default();
+        let mut lines: HashMap<String, usize> = HashMap::new();
+
+        for (index, line) in BufReader::new(readable).lines().enumerate() {
+            let number = index + 1;
+            let line = line.with_context(|| "Failed to read line of config.")?;
+            let invalid = || format!("Invalid login at line {}.", number);
+            let name = if let Some(group) = line.trim().strip_prefix('@') {
+                let group = parse_group(group).with_context(invalid)?;
+                let name = format!("@{}", group.0);
+                logins.groups.push(group);
+                name
+            } else if let Some(acm) = Acm::from_login_line(&line).with_context(invalid)? {
+                let name = acm.0.clone();
+                logins.acms.push(acm);
+                name
+            } else {
+                continue;
+            };
+
+            if let Some(first) = lines.insert(name.clone(), number) {
+                return Err(anyhow!(
+                    "Duplicate name {} at line {} (first defined at line {}).",
+                    name,
+                    number,
+                    first
+                ));
+            }
+        }
+        Ok(logins)
+    }
+
+    /// Parses TOML logins. Each ACM is a table under `acms`, and groups are
+    /// arrays of ACM names or glob patterns under `groups`:
+    ///
+    /// ```toml
+    /// [timeouts]
+    /// command = 60
+    ///
+    /// [acms.CM01]
+    /// host = "10.0.0.1"
+    /// port = 5022
+    /// user = "myuser"
+    /// pass = "p@ss:word"
+    /// description = "East campus"
+    /// tags = ["east"]
+    ///
+    /// [acms.CM02]
+    /// host = "cm02.example.com"
+    /// user = "myuser"
+    /// pass-cmd = "vault kv get -field=password secret/cm02"
+    /// auth = "key"
+    /// key = "/home/myuser/.ssh/id_rsa"
+    /// hostkey = "strict"
+    /// timeouts = { login = 90 }
+    ///
+    /// [groups]
+    /// lab = ["LAB*"]
+    /// ```
+    ///
+    /// Instead of `pass`, a password can be looked up every time the ACM
+    /// connects with `pass-env`, `pass-file`, or `pass-cmd`. See
+    /// [crate::SecretProvider].
+    ///
+    /// Timeouts are in seconds. Those under `[timeouts]` apply to every ACM,
+    /// and an ACM's own `timeouts` override them. Any not set in the file come
+    /// from [Acm::with_timeout_defaults]. Errors name the line of the problem.
+    pub fn from_toml(source: &str) -> Result<Self> {
+        let file: TomlLogins =
+            toml::from_str(source).with_context(|| "Failed to parse TOML logins.")?;
+
+        let mut acms: Vec<_> = file.acms.into_iter().collect();
+        acms.sort_by_key(|(_, acm)| acm.span().start);
+
+        let mut logins = Self {
+            groups: file.groups.into_iter().collect(),
+            ..Default::default()
+        };
+        for (name, config) in acms {
+            let line = line_of(source, config.span().start);
+            let config = config.into_inner();
+            for tag in &config.tags {
+                match logins.groups.iter_mut().find(|(group, _)| group == tag) {
+                    Some((_, members)) => members.push(name.clone()),
+                    None => logins.groups.push((tag.clone(), vec![name.clone()])),
+                }
+            }
+            if config.description.is_some() || !config.tags.is_empty() {
+                let details = AcmDetails {
+                    description: config.description.clone(),
+                    tags: config.tags.clone(),
+                };
+                logins.details.push((name.clone(), details));
+            }
+
+            let acm = config
+                .into_acm(file.timeouts)
+                .with_context(|| format!("Invalid login for {} at line {}.", name, line))?;
+            logins.acms.push((name, acm));
+        }
+        Ok(logins)
+    }
+
+    /// Checks that every group member names an ACM or is a pattern matching
+    /// at least one, without connecting to anything. Parsing already checks
+    /// everything else.
+    pub fn validate(&self) -> Result<()> {
+        for (group, members) in &self.groups {
+            for member in members {
+                if member.starts_with('@') {
+                    return Err(anyhow!(
+                        "Group @{} contains group {}, but groups can't be nested.",
+                        group,
+                        member
+                    ));
+                }
+                let pattern = Pattern::new(member).ok();
+                let matches = self.acms.iter().any(|(name, _)| {
+                    name == member || pattern.as_ref().is_some_and(|p| p.matches(name))
+                });
+                if !matches {
+                    return Err(anyhow!(
+                        "Group @{} member {} matches no ACM.",
+                        group,
+                        member
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses the `name = member member ...` part of a group line.
+fn parse_group(group: &str) -> Result<(String, Vec<String>)> {
+    let (name, members) = group
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Missing '=' in ACM group: @{}", group))?;
+    let name = name.trim();
+    if name.is_empty() || name.contains(char::is_whitespace) {
+        return Err(anyhow!("Invalid ACM group name: @{}", name));
+    }
+
+    Ok((
+        name.into(),
+        members.split_whitespace().map(String::from).collect(),
+    ))
+}
+
+/// The 1-based line number of byte `offset` in `source`.
+fn line_of(source: &str, offset: usize) -> usize {
+    source[..offset.min(source.len())].matches('\n').count() + 1
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TomlLogins {
+    #[serde(default)]
+    timeouts: TomlTimeouts,
+    #[serde(default)]
+    acms: BTreeMap<String, Spanned<TomlAcm>>,
+    #[serde(default)]
+    groups: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct TomlAcm {
+    host: String,
+    port: Option<u16>,
+    user: String,
+    pass: Option<String>,
+    pass_env: Option<String>,
+    pass_file: Option<PathBuf>,
+    pass_cmd: Option<String>,
+    auth: Option<Auth>,
+    key: Option<PathBuf>,
+    key_pass: Option<String>,
+    hostkey: Option<String>,
+    known_hosts: Option<PathBuf>,
+    #[serde(default)]
+    timeouts: TomlTimeouts,
+    description: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+/// How an ACM authenticates. When not given, it is `key` if a key is
+/// configured and `password` otherwise.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+enum Auth {
+    Password,
+    Key,
+    Agent,
+}
+
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+struct TomlTimeouts {
+    connect: Option<u64>,
+    login: Option<u64>,
+    command: Option<u64>,
+    run: Option<u64>,
+}
+
+impl TomlAcm {
+    fn into_acm(self, defaults: TomlTimeouts) -> Result<Acm> {
+        let mut acm = Acm::default();
+        acm.with_host(&self.host).with_user(&self.user);
+        if let Some(port) = self.port {
+            acm.with_port(port);
+        }
+        let mut passwords = 0;
+        if let Some(pass) = &self.pass {
+            acm.with_pass(pass);
+            passwords += 1;
+        }
+        if let Some(name) = &self.pass_env {
+            acm.with_pass_provider(EnvSecret { name: name.clone() });
+            passwords += 1;
+        }
+        if let Some(path) = &self.pass_file {
+            acm.with_pass_provider(FileSecret { path: path.clone() });
+            passwords += 1;
+        }
+        if let Some(command) = &self.pass_cmd {
+            acm.with_pass_provider(CommandSecret {
+                command: command.clone(),
+            });
+            passwords += 1;
+        }
+        if passwords > 1 {
+            return Err(anyhow!(
+                "Only one of pass, pass-env, pass-file, and pass-cmd may be set."
+            ));
+        }
+
+        let auth = match self.auth {
+            Some(auth) => auth,
+            None if self.key.is_some() => Auth::Key,
+            None => Auth::Password,
+        };
+        match (auth, &self.key) {
+            (Auth::Password, None) if passwords == 0 => {
+                return Err(anyhow!("Password authentication needs a pass."))
+            }
+            (Auth::Password, None) => (),
+            (Auth::Key, Some(key)) => {
+                acm.with_key_file(key);
+            }
+            (Auth::Key, None) => return Err(anyhow!("Key authentication needs a key.")),
+            (Auth::Agent, None) => {
+                acm.with_agent();
+            }
+            (_, Some(_)) => return Err(anyhow!("A key is only used with auth = \"key\".")),
+        }
+        if let Some(key_pass) = &self.key_pass {
+            acm.with_key_pass(key_pass);
+        }
+
+        if let Some(mode) = &self.hostkey {
+            acm.with_host_key_check(mode.parse()?);
+        }
+        if let Some(path) = &self.known_hosts {
+            acm.with_known_hosts(path);
+        }
+
+        acm.with_timeout_overrides(self.timeouts.or(defaults).into());
+        Ok(acm)
+    }
+}
+
+impl TomlTimeouts {
+    /// Fills in anything not set here from `defaults`.
+    fn or(self, defaults: Self) -> Self {
+        Self {
+            connect: self.connect.or(defaults.connect),
+            login: self.login.or(defaults.login),
+            command: self.command.or(defaults.command),
+            run: self.run.or(defaults.run),
+        }
+    }
+}
+
+impl From<TomlTimeouts> for TimeoutOverrides {
+    fn from(config: TomlTimeouts) -> Self {
+        Self {
+            connect: config.connect.map(Duration::from_secs),
+            login: config.login.map(Duration::from_secs),
+            command: config.command.map(Duration::from_secs),
+            run: config.run.map(Duration::from_secs),
+        }
+    }
+}
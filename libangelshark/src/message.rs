@@ -1,7 +1,9 @@
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use std::{
+    error::Error,
     fmt::{Display, Formatter, Result as FmtResult},
     io::{BufRead, BufReader, Read},
+    str::FromStr,
 };
 
 /// OSSI Messaging Delimiters
@@ -14,6 +16,42 @@ const NEW_DATA_D: &str = "n";
 const TERMINATOR_D: &str = "t";
 const TAB: &str = "\t";
 
+/// Verbs of commands that only read from an ACM. Commands with any other verb
+/// (ex. `change`, `enable`, `reset`, or `test`) are taken to change it.
+const READ_VERBS: [&str; 4] = ["display", "list", "monitor", "status"];
+
+/// Verbs of commands that only read from an ACM and always finish. `monitor`
+/// only reads, but keeps sending output without ever terminating.
+const READ_ONLY_VERBS: [&str; 3] = ["display", "list", "status"];
+
+/// OSSI Error Codes
+const NO_RECORDS_CODE: u16 = 0x29cf;
+const NO_FIELD: &str = "00000000";
+
+/// OSSI error codes whose [OssiErrorKind] is known.
+const KNOWN_CODES: [(u16, OssiErrorKind); 1] = [(NO_RECORDS_CODE, OssiErrorKind::NotFound)];
+
+/// How the text of errors of each [OssiErrorKind] starts, in lowercase. Texts
+/// are matched from the start, not by words anywhere in them, so that an
+/// error about (say) a busy-hour field isn't taken for a busy object.
+const KNOWN_TEXTS: [(&str, OssiErrorKind); 15] = [
+    ("no records", OssiErrorKind::NotFound),
+    ("object does not exist", OssiErrorKind::NotFound),
+    ("extension not assigned", OssiErrorKind::NotFound),
+    ("identifier not assigned", OssiErrorKind::NotFound),
+    ("object in use", OssiErrorKind::ObjectInUse),
+    ("object is in use", OssiErrorKind::ObjectInUse),
+    ("extension in use", OssiErrorKind::ObjectInUse),
+    ("object busied out", OssiErrorKind::ObjectInUse),
+    ("extension busied out", OssiErrorKind::ObjectInUse),
+    ("permission denied", OssiErrorKind::PermissionDenied),
+    ("command not authorized", OssiErrorKind::PermissionDenied),
+    ("login not authorized", OssiErrorKind::PermissionDenied),
+    ("invalid field", OssiErrorKind::InvalidField),
+    ("invalid value", OssiErrorKind::InvalidField),
+    ("invalid entry", OssiErrorKind::InvalidField),
+];
+
 /// An OSSI protocol message. Used for input and output to and from the ACM. The
 /// OSSI protocol is proprietary, and little documented. Here is a brief
 /// overview. Every message consists of a single command and a
@@ -53,7 +91,7 @@ impl Message {
     fn add_fields(&mut self, fields: Vec<String>) -> &mut Self {
         if !fields.is_empty() {
             if let Some(ref mut existing) = self.fields {
-                existing.extend(fields.into_iter());
+                existing.extend(fields);
             } else {
                 self.fields = Some(fields);
             }
@@ -74,6 +112,51 @@ impl Message {
         self
     }
 
+    /// Parses this message's error (if any) into an [OssiError]. Returns
+    /// `None` if there is no error or it is not in the usual OSSI format.
+    pub fn ossi_error(&self) -> Option<OssiError> {
+        self.error.as_deref()?.parse().ok()
+    }
+
+    /// Whether the command may change the ACM, judging by its verb. Every
+    /// command changes it except those that only read from it (`display`,
+    /// `list`, `monitor`, and `status`). Abbreviated verbs of at least three
+    /// letters (ex. `dis station 1000`) count.
+    pub fn is_change(&self) -> bool {
+        !self.has_verb(&READ_VERBS)
+    }
+
+    /// Whether the command only reads from the ACM and finishes (`list`,
+    /// `display`, or `status`), judging by its verb like [Self::is_change].
+    /// Commands with any other verb, or none, are not read-only. Neither are
+    /// `monitor` commands, which never finish.
+    pub fn is_read_only(&self) -> bool {
+        self.has_verb(&READ_ONLY_VERBS)
+    }
+
+    fn has_verb(&self, verbs: &[&str]) -> bool {
+        let verb = self
+            .command
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+        verbs
+            .iter()
+            .any(|v| *v == verb || (verb.len() >= 3 && v.starts_with(&verb)))
+    }
+
+    /// Returns the requested fields that are not valid OSSI field IDs (eight
+    /// hexadecimal digits).
+    pub fn invalid_fields(&self) -> Vec<&str> {
+        self.fields
+            .iter()
+            .flatten()
+            .filter(|f| f.len() != 8 || !f.chars().all(|c| c.is_ascii_hexdigit()))
+            .map(String::as_str)
+            .collect()
+    }
+
     /// Reads from `readable`, parsing lines as Angelshark-formatted OSSI input.
     /// This closely follows the OSSI spec, but also parses ACM names/labels on
     /// lines beginning with `'a'`. The spec looks like this:
@@ -86,6 +169,9 @@ impl Message {
     /// t
     /// ...
     /// ```
+    ///
+    /// Labels are passed on as they are. An [crate::AcmRunner] expands groups
+    /// (ex. `a@east`) and glob patterns (ex. `aCM*`) when inputs are queued.
     pub fn from_input(readable: impl Read) -> Result<Vec<(String, Self)>> {
         let mut data = Vec::new();
         let mut input = Self::default();
@@ -127,44 +213,311 @@ impl Message {
 
     /// Reads `readable` and parses lines as ACM OSSI output. This should exactly follow the OSSI spec.
     pub fn from_output(readable: impl Read) -> Result<Vec<Self>> {
-        let mut data = Vec::new();
-        let mut output = Self::default();
-        let mut outputs = Vec::new();
+        Self::stream_output(readable).collect()
+    }
 
-        for line in BufReader::new(readable).lines() {
-            let line = line.with_context(|| "Failed to read line of output.")?;
+    /// Like [Self::from_output], but lazily parses each [Message] as it is
+    /// read from `readable` instead of reading everything up front.
+    pub fn stream_output<R: Read>(readable: R) -> OutputMessages<BufReader<R>> {
+        Self::stream_rows(readable).messages()
+    }
+
+    /// Like [Self::stream_output], but yields individual [OutputRow]s (such as
+    /// each data entry) as soon as they are read, instead of whole messages.
+    pub fn stream_rows<R: Read>(readable: R) -> OutputRows<BufReader<R>> {
+        OutputRows::new(BufReader::new(readable))
+    }
+
+    /// Reads lines from `reader` until one complete OSSI output message (up to
+    /// and including its terminator) has been parsed. Returns `None` if
+    /// `reader` reaches EOF before a terminator is read.
+    pub(crate) fn read_output(reader: &mut impl BufRead) -> Result<Option<Self>> {
+        OutputRows::new(reader).messages().next().transpose()
+    }
+}
+
+/// An error returned by the ACM for an OSSI command, parsed from
+/// [Message::error]. OSSI errors look like this:
+///
+/// ```text
+/// (index field_address hex_code text)
+/// 1 00000000 29cf No records match the specified query options
+/// ```
+///
+/// The field address is `00000000` for errors that are not about a particular
+/// field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OssiError {
+    /// Which entry of the input the error refers to, starting at 1.
+    pub index: usize,
+    /// Hexadecimal address of the field the error refers to.
+    pub field: String,
+    /// Numeric error code (printed in hexadecimal by the ACM).
+    pub code: u16,
+    /// Human-readable error text.
+    pub text: String,
+}
+
+/// Broad categories of [OssiError]s, for deciding how to handle them without
+/// matching on codes or text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OssiErrorKind {
+    /// The requested object (station, agent, etc.) does not exist.
+    NotFound,
+    /// The object is busy or in use and must be released or busied out first.
+    ObjectInUse,
+    /// The login is not permitted to run the command.
+    PermissionDenied,
+    /// A field address or field value was rejected.
+    InvalidField,
+    /// Any other error.
+    Other,
+}
+
+impl OssiErrorKind {
+    /// A short, stable name for the kind, e.g. `not-found`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::NotFound => "not-found",
+            Self::ObjectInUse => "object-in-use",
+            Self::PermissionDenied => "permission-denied",
+            Self::InvalidField => "invalid-field",
+            Self::Other => "other",
+        }
+    }
+}
+
+impl OssiError {
+    /// Classifies the error. Since Avaya does not publish a full list of OSSI
+    /// error codes, this goes by well-known codes first, then by how the error
+    /// text starts. Other errors about a particular field are
+    /// [OssiErrorKind::InvalidField].
+    pub fn kind(&self) -> OssiErrorKind {
+        let text = self.text.trim().to_lowercase();
+
+        if let Some((_, kind)) = KNOWN_CODES.iter().find(|(code, _)| *code == self.code) {
+            *kind
+        } else if let Some((_, kind)) = KNOWN_TEXTS
+            .iter()
+            .find(|(start, _)| text.starts_with(start))
+        {
+            *kind
+        } else if self.field != NO_FIELD {
+            OssiErrorKind::InvalidField
+        } else {
+            OssiErrorKind::Other
+        }
+    }
+
+    /// Whether the requested object does not exist.
+    pub fn is_not_found(&self) -> bool {
+        self.kind() == OssiErrorKind::NotFound
+    }
+
+    /// Whether the object is busy or in use.
+    pub fn is_object_in_use(&self) -> bool {
+        self.kind() == OssiErrorKind::ObjectInUse
+    }
+
+    /// Whether the login is not permitted to run the command.
+    pub fn is_permission_denied(&self) -> bool {
+        self.kind() == OssiErrorKind::PermissionDenied
+    }
+
+    /// Whether a field address or value was rejected.
+    pub fn is_invalid_field(&self) -> bool {
+        self.kind() == OssiErrorKind::InvalidField
+    }
+}
+
+impl FromStr for OssiError {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.trim().splitn(4, ' ');
+        let (index, field, code) = (parts.next(), parts.next(), parts.next());
+
+        match (index, field, code) {
+            (Some(_), Some(field), Some(_))
+                if field.len() != 8 || !field.chars().all(|c| c.is_ascii_hexdigit()) =>
+            {
+                Err(anyhow!(
+                    "Failed to parse OSSI error field address: {}",
+                    field
+                ))
+            }
+            (Some(index), Some(field), Some(code)) => Ok(Self {
+                index: index
+                    .parse()
+                    .with_context(|| format!("Failed to parse OSSI error index: {}", index))?,
+                field: field.into(),
+                code: u16::from_str_radix(code, 16)
+                    .with_context(|| format!("Failed to parse OSSI error code: {}", code))?,
+                text: parts.next().unwrap_or_default().into(),
+            }),
+            _ => Err(anyhow!("Malformed OSSI error: {}", s)),
+        }
+    }
+}
+
+impl Display for OssiError {
+    /// Formats the error the same way the ACM does.
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(
+            f,
+            "{} {} {:04x} {}",
+            self.index, self.field, self.code, self.text
+        )
+    }
+}
+
+impl Error for OssiError {}
+
+/// A piece of OSSI output, as parsed by [OutputRows]. Every output message
+/// starts with a [OutputRow::Command] and ends with an [OutputRow::End].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputRow {
+    /// The command the following rows are output for.
+    Command(String),
+    /// An error returned by the ACM for the command.
+    Error(String),
+    /// Hexadecimal field addresses of the data entries that follow. A message
+    /// may have more than one of these, in which case they should be joined.
+    Fields(Vec<String>),
+    /// One complete data entry.
+    Data(Vec<String>),
+    /// The end of the current message.
+    End,
+}
+
+/// An iterator of [OutputRow]s parsed from OSSI output. Created by
+/// [Message::stream_rows] and [crate::Acm::run_stream]. Stops after the first
+/// error.
+pub struct OutputRows<R> {
+    reader: R,
+    data: Vec<String>,
+    ended: bool,
+    failed: bool,
+}
+
+impl<R: BufRead> OutputRows<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            data: Vec::new(),
+            ended: false,
+            failed: false,
+        }
+    }
+
+    /// Groups the remaining rows into whole [Message]s.
+    pub fn messages(self) -> OutputMessages<R> {
+        OutputMessages { rows: self }
+    }
+
+    /// Reads lines until a row is complete. Returns `None` at EOF.
+    fn read_row(&mut self) -> Result<Option<OutputRow>> {
+        if self.ended {
+            self.ended = false;
+            return Ok(Some(OutputRow::End));
+        }
+
+        let mut line = String::new();
+
+        loop {
+            line.clear();
+            if self
+                .reader
+                .read_line(&mut line)
+                .with_context(|| "Failed to read line of output.")?
+                == 0
+            {
+                return Ok(None);
+            }
+
+            let line = line.trim_end_matches('\n').trim_end_matches('\r');
             let (delim, content) = (line.get(0..1).unwrap_or_default(), line.get(1..));
 
             match (delim, content) {
                 (COMMAND_D, Some(c)) => {
-                    output.command = c.into();
+                    return Ok(Some(OutputRow::Command(c.into())));
                 }
                 (ERROR_D, Some(e)) => {
-                    output.error = Some(e.into());
+                    return Ok(Some(OutputRow::Error(e.into())));
                 }
                 (FIELD_D, Some(f)) => {
-                    output.add_fields(f.split(TAB).map(String::from).collect());
+                    return Ok(Some(OutputRow::Fields(
+                        f.split(TAB).map(String::from).collect(),
+                    )));
                 }
                 (DATA_D, Some(d)) => {
-                    data.extend(d.split(TAB).map(String::from));
+                    self.data.extend(d.split(TAB).map(String::from));
                 }
-                (NEW_DATA_D, _) => {
-                    output.add_data_entry(data);
-                    data = Vec::new();
+                (NEW_DATA_D, _) if !self.data.is_empty() => {
+                    return Ok(Some(OutputRow::Data(std::mem::take(&mut self.data))));
+                }
+                (TERMINATOR_D, _) if !self.data.is_empty() => {
+                    self.ended = true;
+                    return Ok(Some(OutputRow::Data(std::mem::take(&mut self.data))));
                 }
                 (TERMINATOR_D, _) => {
-                    output.add_data_entry(data);
-                    data = Vec::new();
-                    outputs.push(output);
-                    output = Self::default();
+                    return Ok(Some(OutputRow::End));
                 }
                 _ => {
-                    // Ignore unknown identifiers and blank lines.
+                    // Ignore unknown identifiers, blank lines, and empty data entries.
+                }
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for OutputRows<R> {
+    type Item = Result<OutputRow>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        let row = self.read_row().transpose();
+        self.failed = matches!(row, Some(Err(_)));
+        row
+    }
+}
+
+/// An iterator of whole [Message]s parsed from OSSI output. Created by
+/// [Message::stream_output] and [OutputRows::messages]. A message that is not
+/// terminated before the output ends is discarded.
+pub struct OutputMessages<R> {
+    rows: OutputRows<R>,
+}
+
+impl<R: BufRead> Iterator for OutputMessages<R> {
+    type Item = Result<Message>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut output = Message::default();
+
+        for row in self.rows.by_ref() {
+            match row {
+                Err(e) => return Some(Err(e)),
+                Ok(OutputRow::Command(c)) => {
+                    output.command = c;
+                }
+                Ok(OutputRow::Error(e)) => {
+                    output.error = Some(e);
                 }
+                Ok(OutputRow::Fields(f)) => {
+                    output.add_fields(f);
+                }
+                Ok(OutputRow::Data(d)) => {
+                    output.add_data_entry(d);
+                }
+                Ok(OutputRow::End) => return Some(Ok(output)),
             }
         }
 
-        Ok(outputs)
+        None
     }
 }
 
@@ -192,3 +545,183 @@ impl Display for Message {
         writeln!(f, "{}\nt", message)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Message, OssiError, OssiErrorKind};
+
+    #[test]
+    fn verbs_are_classified() {
+        let reads = [
+            "display station 1000",
+            "dis station 1000",
+            "list station",
+            "LIST STATION",
+            "status trunk 1",
+            "sta trunk 1",
+        ];
+        let monitors = ["monitor traffic trunk-groups", "mon traffic trunk-groups"];
+        let changes = [
+            "add station next",
+            "busyout station 1000",
+            "campon-busyout media-processor 1",
+            "cancel hardware-group",
+            "cha station 1000",
+            "change station 1000",
+            "clear measurements security-violations",
+            "disable test-number 1",
+            "duplicate station 1000",
+            "enable test-number 1",
+            "refresh ip-route all",
+            "release station 1000",
+            "remove station 1000",
+            "reset board 01A01",
+            "save translation",
+            "set time",
+            "test station 1000",
+            "ls station",
+            "",
+        ];
+
+        for command in reads {
+            let message = Message::new(command);
+            assert!(
+                message.is_read_only() && !message.is_change(),
+                "{}",
+                command
+            );
+        }
+        for command in monitors {
+            let message = Message::new(command);
+            assert!(
+                !message.is_read_only() && !message.is_change(),
+                "{}",
+                command
+            );
+        }
+        for command in changes {
+            let message = Message::new(command);
+            assert!(
+                !message.is_read_only() && message.is_change(),
+                "{:?}",
+                command
+            );
+        }
+    }
+
+    #[test]
+    fn ossi_errors_parse() {
+        let error: OssiError = "2 8005ff00 2a1b Invalid value; enter a number"
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            error,
+            OssiError {
+                index: 2,
+                field: "8005ff00".into(),
+                code: 0x2a1b,
+                text: "Invalid value; enter a number".into(),
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "2 8005ff00 2a1b Invalid value; enter a number"
+        );
+        assert_eq!("1 00000000 0001".parse::<OssiError>().unwrap().text, "");
+    }
+
+    #[test]
+    fn malformed_ossi_errors_are_rejected() {
+        let cases = [
+            ("", "Malformed OSSI error"),
+            ("1 00000000", "Malformed OSSI error"),
+            (
+                "x 00000000 29cf No records",
+                "Failed to parse OSSI error index",
+            ),
+            (
+                "1 8005ff 29cf Invalid value",
+                "Failed to parse OSSI error field address",
+            ),
+            (
+                "1 8005ff0g 29cf Invalid value",
+                "Failed to parse OSSI error field address",
+            ),
+            (
+                "1 00000000 xyz No records",
+                "Failed to parse OSSI error code",
+            ),
+            (
+                "1 00000000 129cf No records",
+                "Failed to parse OSSI error code",
+            ),
+        ];
+
+        for (line, expected) in cases {
+            let error = line.parse::<OssiError>().unwrap_err().to_string();
+            assert!(error.starts_with(expected), "{:?}: {}", line, error);
+        }
+    }
+
+    #[test]
+    fn ossi_errors_are_classified() {
+        let cases = [
+            (
+                "1 00000000 29cf No records match the specified query options",
+                OssiErrorKind::NotFound,
+            ),
+            (
+                "1 00000000 0001 Extension not assigned",
+                OssiErrorKind::NotFound,
+            ),
+            (
+                "1 00000000 0001 Object does not exist",
+                OssiErrorKind::NotFound,
+            ),
+            (
+                "1 00000000 0001 Object in use; please try later",
+                OssiErrorKind::ObjectInUse,
+            ),
+            (
+                "1 00000000 0001 Extension busied out",
+                OssiErrorKind::ObjectInUse,
+            ),
+            (
+                "1 00000000 0001 Permission denied",
+                OssiErrorKind::PermissionDenied,
+            ),
+            (
+                "1 00000000 0001 Command not authorized for this login",
+                OssiErrorKind::PermissionDenied,
+            ),
+            (
+                "1 00000000 0001 Invalid entry; try again",
+                OssiErrorKind::InvalidField,
+            ),
+            (
+                "1 8005ff00 0001 Value out of range",
+                OssiErrorKind::InvalidField,
+            ),
+            ("1 00000000 0001 Command aborted", OssiErrorKind::Other),
+            // Words that only appear later in the text don't decide the kind.
+            (
+                "1 00000000 0001 Trunk group busy hour not measured",
+                OssiErrorKind::Other,
+            ),
+            (
+                "1 00000000 0001 Cannot change field while denied",
+                OssiErrorKind::Other,
+            ),
+            (
+                "1 00000000 0001 Report not found for this period",
+                OssiErrorKind::Other,
+            ),
+        ];
+
+        for (line, expected) in cases {
+            let error: OssiError = line.parse().unwrap();
+            assert_eq!(error.kind(), expected, "{}", line);
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use crate::{Acm, AcmRunner, ManualOutput, Message, ParallelIterator, ReportOutput, RunOutput};
+use anyhow::Result;
+use std::panic;
+use tokio::task;
+
+impl Acm {
+    /// Like [Self::run], but runs on Tokio's blocking thread pool so that the
+    /// SSH session doesn't hold up the runtime's worker threads.
+    pub async fn run_async(&self, inputs: &[Message]) -> Result<Vec<Message>> {
+        let (acm, inputs) = (self.clone(), inputs.to_vec());
+        blocking(move || acm.run(&inputs)).await
+    }
+
+    /// Like [Self::manual], but runs on Tokio's blocking thread pool.
+    pub async fn manual_async(&self, inputs: &[Message]) -> Result<String> {
+        let (acm, inputs) = (self.clone(), inputs.to_vec());
+        blocking(move || acm.manual(&inputs)).await
+    }
+}
+
+impl AcmRunner {
+    /// Like [Self::run], but runs on Tokio's blocking thread pool and collects
+    /// the outputs in input order.
+    pub async fn run_async(self) -> Vec<RunOutput> {
+        blocking(move || self.run().collect()).await
+    }
+
+    /// Like [Self::run_reports], but runs on Tokio's blocking thread pool and
+    /// collects the reports in input order.
+    pub async fn run_reports_async(self) -> Vec<ReportOutput> {
+        blocking(move || self.run_reports().collect()).await
+    }
+
+    /// Like [Self::run_cached], but runs on Tokio's blocking thread pool and
+    /// collects the outputs in input order.
+    pub async fn run_cached_async(self) -> Vec<RunOutput> {
+        blocking(move || self.run_cached().collect()).await
+    }
+
+    /// Like [Self::manuals], but runs on Tokio's blocking thread pool and
+    /// collects the outputs in input order.
+    pub async fn manuals_async(self) -> Vec<ManualOutput> {
+        blocking(move || self.manuals().collect()).await
+    }
+}
+
+/// Calls `run` on Tokio's blocking thread pool and waits for it without
+/// blocking the current task. Panics in `run` are passed on to the caller.
+async fn blocking<T: Send + 'static>(run: impl FnOnce() -> T + Send + 'static) -> T {
+    match task::spawn_blocking(run).await {
+        Ok(output) => output,
+        Err(e) if e.is_panic() => panic::resume_unwind(e.into_panic()),
+        Err(e) => panic!("Blocking run never finished: {}", e),
+    }
+}
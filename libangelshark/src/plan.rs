@@ -0,0 +1,101 @@
+use crate::{Message, UnknownAcm};
+use std::fmt::{self, Display};
+
+/// What an [crate::AcmRunner] would run, worked out without logging in to
+/// anything. See [crate::AcmRunner::plan].
+#[derive(Debug, Default, Clone)]
+pub struct Plan {
+    /// Jobs with queued inputs, in input order.
+    pub jobs: Vec<PlannedJob>,
+    /// Inputs queued for job names with no registered ACM, which would not
+    /// run, in input order.
+    pub unknown: Vec<UnknownAcm>,
+}
+
+/// The inputs that would run on one ACM.
+#[derive(Debug, Clone)]
+pub struct PlannedJob {
+    pub job_name: String,
+    /// The user, host, and port that would be logged in to.
+    pub login: String,
+    pub inputs: Vec<PlannedInput>,
+}
+
+/// One input that would run, and anything worth knowing about it first.
+#[derive(Debug, Clone)]
+pub struct PlannedInput {
+    pub input: Message,
+    /// Whether the command changes the ACM. See [Message::is_change].
+    pub is_change: bool,
+    /// Requested fields that are not valid field IDs. See
+    /// [Message::invalid_fields].
+    pub invalid_fields: Vec<String>,
+}
+
+impl PlannedInput {
+    pub(crate) fn new(input: &Message) -> Self {
+        Self {
+            is_change: input.is_change(),
+            invalid_fields: input
+                .invalid_fields()
+                .into_iter()
+                .map(String::from)
+                .collect(),
+            input: input.clone(),
+        }
+    }
+}
+
+impl Plan {
+    /// Whether everything would run as queued: every job name is registered
+    /// and every field ID is valid. Changes are allowed.
+    pub fn is_valid(&self) -> bool {
+        self.unknown.is_empty() && self.inputs().all(|i| i.invalid_fields.is_empty())
+    }
+
+    /// How many inputs would change an ACM.
+    pub fn changes(&self) -> usize {
+        self.inputs().filter(|i| i.is_change).count()
+    }
+
+    fn inputs(&self) -> impl Iterator<Item = &PlannedInput> {
+        self.jobs.iter().flat_map(|job| &job.inputs)
+    }
+}
+
+impl Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for job in &self.jobs {
+            writeln!(f, "{} ({})", job.job_name, job.login)?;
+            for input in &job.inputs {
+                write!(f, "    {}", input.input.command)?;
+                if let Some(fields) = &input.input.fields {
+                    write!(f, " [fields: {}]", fields.join(" "))?;
+                }
+                if input.is_change {
+                    write!(f, " [changes ACM]")?;
+                }
+                if !input.invalid_fields.is_empty() {
+                    write!(f, " [invalid fields: {}]", input.invalid_fields.join(" "))?;
+                }
+                writeln!(f)?;
+            }
+        }
+        for unknown in &self.unknown {
+            writeln!(f, "{} (unknown ACM)", unknown.job_name)?;
+            for input in &unknown.inputs {
+                writeln!(f, "    {}", input.command)?;
+            }
+            writeln!(f, "    {}", unknown)?;
+        }
+
+        write!(
+            f,
+            "{} ACM(s), {} input(s), {} change(s), {} unknown ACM input(s)",
+            self.jobs.len(),
+            self.inputs().count(),
+            self.changes(),
+            self.unknown.iter().map(|u| u.inputs.len()).sum::<usize>()
+        )
+    }
+}
@@ -0,0 +1,206 @@
+use crate::{
+    acm::run_cached,
+    session::OssiSession,
+    timeout::{Deadline, CANCEL_POLL},
+    Acm, CancelToken, Cancelled, Message, RunReport,
+};
+use anyhow::Result;
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+/// Keeps logged-in OSSI sessions open so that they can be reused across runs
+/// instead of opening a new SSH connection (and SAT login) for every run.
+/// Sessions are keyed by login, so every [Acm] with the same user, host, and
+/// port shares them. Clones share the same sessions.
+///
+/// At most `max_sessions` sessions are open per login at a time. Runs that
+/// need a session while all of them are busy wait for one to be returned, or
+/// until the run is cancelled or times out.
+/// Idle sessions are health-checked before reuse, and sessions idle longer
+/// than `max_idle` are logged off.
+#[derive(Clone)]
+pub struct SessionPool {
+    inner: Arc<PoolInner>,
+}
+
+struct PoolInner {
+    logins: Mutex<HashMap<String, Login>>,
+    returned: Condvar,
+    max_sessions: usize,
+    max_idle: Duration,
+}
+
+/// Sessions for one login.
+#[derive(Default)]
+struct Login {
+    idle: Vec<OssiSession>,
+    open: usize,
+}
+
+impl Debug for SessionPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionPool")
+            .field("max_sessions", &self.inner.max_sessions)
+            .field("max_idle", &self.inner.max_idle)
+            .finish()
+    }
+}
+
+impl SessionPool {
+    /// Creates an empty pool that keeps up to `max_sessions` sessions open per
+    /// login, and logs off sessions that have been idle for `max_idle`.
+    pub fn new(max_sessions: usize, max_idle: Duration) -> Self {
+        Self {
+            inner: Arc::new(PoolInner {
+                logins: Mutex::new(HashMap::new()),
+                returned: Condvar::new(),
+                max_sessions: max_sessions.max(1),
+                max_idle,
+            }),
+        }
+    }
+
+    /// Like [Acm::run], but runs the [Message]s on a pooled session. The
+    /// session is returned to the pool afterwards instead of logging off. If
+    /// anything goes wrong (other than the run being cancelled between
+    /// commands), the session is discarded. Time spent waiting for a busy
+    /// pool counts against the run timeout.
+    pub fn run(&self, acm: &Acm, inputs: &[Message]) -> Result<Vec<Message>> {
+        self.run_report(acm, inputs).into_outputs()
+    }
+
+    /// Like [Self::run], but returns a [RunReport]. Connect and login times
+    /// are only recorded if a new session had to be opened.
+    pub fn run_report(&self, acm: &Acm, inputs: &[Message]) -> RunReport {
+        let started = Instant::now();
+        let mut report = RunReport::new(inputs);
+        report.attempts = 1;
+        report.error = self.run_into(acm, &mut report).err();
+        report.elapsed = started.elapsed();
+        report
+    }
+
+    /// Runs the inputs of `report` on a pooled session, recording the outputs
+    /// and timings in it.
+    pub(crate) fn run_into(&self, acm: &Acm, report: &mut RunReport) -> Result<()> {
+        let key = acm.login_key();
+        let deadline = Deadline::new(&acm.timeouts());
+        let mut session = self.checkout(&key, acm, &deadline, report)?;
+
+        match session.run(
+            report,
+            acm.timeouts().command,
+            &deadline,
+            acm.cancel_token(),
+        ) {
+            Ok(()) => {
+                self.checkin(&key, Some(session));
+                Ok(())
+            }
+            Err(e) if e.is::<Cancelled>() => {
+                self.checkin(&key, Some(session));
+                Err(e)
+            }
+            Err(e) => {
+                drop(session);
+                self.checkin(&key, None);
+                Err(e)
+            }
+        }
+    }
+
+    /// Like [Self::run], but caches results with a timed cache of thirty
+    /// minutes. Shares its cache with [Acm::run_cached].
+    pub fn run_cached(&self, acm: &Acm, inputs: &[Message]) -> Result<Vec<Message>> {
+        Ok(run_cached(acm, Some(self), inputs)?.value)
+    }
+
+    /// Logs off every idle session that has exceeded the idle limit or fails
+    /// its health check. Sessions in use are unaffected. Sessions are also
+    /// checked whenever they are taken from the pool, so this only needs to be
+    /// called to free ACM logins held by a pool that is not being used.
+    pub fn prune(&self) {
+        let candidates: Vec<(String, OssiSession)> = {
+            let mut logins = self.lock();
+            logins
+                .iter_mut()
+                .flat_map(|(key, login)| login.idle.drain(..).map(move |s| (key.clone(), s)))
+                .collect()
+        };
+
+        for (key, mut session) in candidates {
+            if self.is_reusable(&mut session) {
+                self.checkin(&key, Some(session));
+            } else {
+                drop(session);
+                self.checkin(&key, None);
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Login>> {
+        self.inner.logins.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn is_reusable(&self, session: &mut OssiSession) -> bool {
+        session.last_used.elapsed() < self.inner.max_idle && session.is_healthy()
+    }
+
+    /// Takes an idle, healthy session for `key` from the pool, or logs in a
+    /// new one if the login is below its session limit. Otherwise, waits for
+    /// a session to be returned.
+    fn checkout(
+        &self,
+        key: &str,
+        acm: &Acm,
+        deadline: &Deadline,
+        report: &mut RunReport,
+    ) -> Result<OssiSession> {
+        loop {
+            CancelToken::check(acm.cancel_token())?;
+            let remaining = deadline.remaining()?;
+            let mut logins = self.lock();
+            let login = logins.entry(key.into()).or_default();
+
+            if let Some(mut session) = login.idle.pop() {
+                drop(logins);
+                if self.is_reusable(&mut session) {
+                    return Ok(session);
+                }
+                drop(session);
+                self.checkin(key, None);
+            } else if login.open < self.inner.max_sessions {
+                login.open += 1;
+                drop(logins);
+                return OssiSession::open(acm, deadline, report)
+                    .inspect_err(|_| self.checkin(key, None));
+            } else {
+                let wait = remaining.map_or(CANCEL_POLL, |remaining| remaining.min(CANCEL_POLL));
+                let _logins = self
+                    .inner
+                    .returned
+                    .wait_timeout(logins, wait)
+                    .unwrap_or_else(|e| e.into_inner());
+            }
+        }
+    }
+
+    /// Returns a session for `key` to the pool. If `session` is `None`, the
+    /// session was discarded and its slot is freed. Every waiting run is
+    /// woken, since they may be waiting for other logins.
+    fn checkin(&self, key: &str, session: Option<OssiSession>) {
+        let mut logins = self.lock();
+        let login = logins.entry(key.into()).or_default();
+
+        match session {
+            Some(session) => login.idle.push(session),
+            None => login.open = login.open.saturating_sub(1),
+        }
+
+        self.inner.returned.notify_all();
+    }
+}
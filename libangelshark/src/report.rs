@@ -0,0 +1,137 @@
+use crate::Message;
+use anyhow::{anyhow, Result};
+use std::{
+    fmt::{self, Display},
+    time::Duration,
+};
+
+/// What happened during one run of [Message]s on an ACM: how long it took to
+/// connect and log in, and every input paired with its output, timing, and
+/// byte counts. Inputs that never got an output (ex. because the run failed
+/// or the ACM closed the session) have `None` for theirs.
+#[derive(Debug, Default)]
+pub struct RunReport {
+    /// Time spent opening the TCP connection. `None` if no new connection was
+    /// needed (ex. a pooled session was reused).
+    pub connect: Option<Duration>,
+    /// Time spent on the SSH handshake, authentication, and reaching the OSSI
+    /// prompt. `None` if no new login was needed.
+    pub login: Option<Duration>,
+    /// Every input, in order.
+    pub commands: Vec<CommandReport>,
+    /// How many times the run was attempted. More than one if it was retried.
+    pub attempts: u32,
+    /// Time the whole run took, including retries and logging off.
+    pub elapsed: Duration,
+    /// Why the run failed, if it did.
+    pub error: Option<anyhow::Error>,
+}
+
+/// One input of a [RunReport] and what came of it.
+#[derive(Debug, Clone)]
+pub struct CommandReport {
+    pub input: Message,
+    pub output: Option<Message>,
+    /// Time from sending the input to reading the end of its output.
+    pub elapsed: Duration,
+    pub bytes_sent: usize,
+    pub bytes_received: usize,
+}
+
+impl RunReport {
+    /// Starts a report for running `inputs`, none of which have run yet.
+    pub(crate) fn new(inputs: &[Message]) -> Self {
+        Self {
+            commands: inputs
+                .iter()
+                .map(|input| CommandReport {
+                    input: input.clone(),
+                    output: None,
+                    elapsed: Duration::ZERO,
+                    bytes_sent: 0,
+                    bytes_received: 0,
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Clears everything recorded by a failed attempt before retrying.
+    pub(crate) fn reset(&mut self) {
+        self.connect = None;
+        self.login = None;
+        for command in &mut self.commands {
+            command.output = None;
+            command.elapsed = Duration::ZERO;
+            command.bytes_sent = 0;
+            command.bytes_received = 0;
+        }
+    }
+
+    /// Total bytes of input sent to the ACM.
+    pub fn bytes_sent(&self) -> usize {
+        self.commands.iter().map(|c| c.bytes_sent).sum()
+    }
+
+    /// Total bytes of output read from the ACM.
+    pub fn bytes_received(&self) -> usize {
+        self.commands.iter().map(|c| c.bytes_received).sum()
+    }
+
+    /// Returns the outputs, as they would have been returned by
+    /// [crate::Acm::run], or the error if the run failed.
+    pub fn into_outputs(self) -> Result<Vec<Message>> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        self.commands
+            .into_iter()
+            .map(|c| {
+                c.output
+                    .ok_or_else(|| anyhow!("No output for input: {}", c.input.command))
+            })
+            .collect()
+    }
+}
+
+impl Display for RunReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let done = self.commands.iter().filter(|c| c.output.is_some()).count();
+        write!(
+            f,
+            "{}/{} commands in {:.3}s",
+            done,
+            self.commands.len(),
+            self.elapsed.as_secs_f64()
+        )?;
+        if let Some(connect) = self.connect {
+            write!(f, ", connect {:.3}s", connect.as_secs_f64())?;
+        }
+        if let Some(login) = self.login {
+            write!(f, ", login {:.3}s", login.as_secs_f64())?;
+        }
+        write!(
+            f,
+            ", {} attempt(s), {} bytes sent, {} bytes received",
+            self.attempts,
+            self.bytes_sent(),
+            self.bytes_received()
+        )
+    }
+}
+
+impl Display for CommandReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.output {
+            Some(_) => write!(
+                f,
+                "{}: {:.3}s, {} bytes sent, {} bytes received",
+                self.input.command,
+                self.elapsed.as_secs_f64(),
+                self.bytes_sent,
+                self.bytes_received
+            ),
+            None => write!(f, "{}: no output", self.input.command),
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use crate::CancelToken;
+use anyhow::Result;
+use std::{
+    collections::hash_map::RandomState,
+    error::Error,
+    fmt::{Display, Formatter, Result as FmtResult},
+    hash::{BuildHasher, Hash, Hasher},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// The stage of logging in to an ACM that failed. Attached (via
+/// [anyhow::Error::context]) to errors from before any inputs were sent, so
+/// they can be retried safely.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConnectFailure {
+    /// No TCP connection could be opened to any of the ACM's addresses.
+    Connect,
+    /// The SSH handshake failed.
+    Handshake,
+    /// The SSH login succeeded, but the OSSI terminal prompt was never
+    /// reached (ex. the ACM is out of SAT logins).
+    Prompt,
+}
+
+impl Display for ConnectFailure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(match self {
+            Self::Connect => "Failed to open TCP stream to host. Make sure the config is correct and the host is otherwise reachable.",
+            Self::Handshake => "SSH handshake failed.",
+            Self::Prompt => "Never reached OSSI term prompt.",
+        })
+    }
+}
+
+impl Error for ConnectFailure {}
+
+impl ConnectFailure {
+    /// Returns the stage `error` failed at, if it happened while logging in.
+    pub fn of(error: &anyhow::Error) -> Option<Self> {
+        error.downcast_ref().copied()
+    }
+}
+
+/// Controls how [crate::AcmRunner] retries runs that fail while logging in.
+/// Only [ConnectFailure]s are retried, since they happen before any inputs
+/// are sent. A run that fails after its inputs were sent (including change
+/// commands) is never retried.
+///
+/// Between attempts, the runner waits with exponential backoff: the first
+/// wait is around `initial_backoff`, and each wait after that doubles, up to
+/// `max_backoff`. Waits are randomized between half and all of that time so
+/// that ACMs aren't retried in lockstep.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first.
+    pub max_attempts: u32,
+    /// Wait before the first retry.
+    pub initial_backoff: Duration,
+    /// Longest wait between attempts.
+    pub max_backoff: Duration,
+    /// Which login failures are retried.
+    pub retryable: Vec<ConnectFailure>,
+}
+
+impl Default for RetryPolicy {
+    /// Three attempts, starting at one second of backoff, for all
+    /// [ConnectFailure]s.
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(30),
+            retryable: vec![
+                ConnectFailure::Connect,
+                ConnectFailure::Handshake,
+                ConnectFailure::Prompt,
+            ],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Whether `error` is a login failure that this policy retries.
+    pub fn is_retryable(&self, error: &anyhow::Error) -> bool {
+        ConnectFailure::of(error).is_some_and(|failure| self.retryable.contains(&failure))
+    }
+
+    /// Returns how long to wait after failed attempt number `attempt`
+    /// (starting at 1).
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_backoff);
+        let half = backoff / 2;
+        half + half.mul_f64(jitter())
+    }
+
+    /// Calls `run` until it succeeds, fails with an error that isn't
+    /// retryable, or runs out of attempts. Stops waiting early if `cancel` is
+    /// cancelled.
+    pub(crate) fn run<T>(
+        &self,
+        cancel: Option<&CancelToken>,
+        mut run: impl FnMut() -> Result<T>,
+    ) -> Result<T> {
+        let mut attempt = 1;
+        loop {
+            match run() {
+                Err(e) if attempt < self.max_attempts && self.is_retryable(&e) => {
+                    sleep(self.backoff(attempt), cancel);
+                    CancelToken::check(cancel)?;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Returns a pseudo-random number in `[0, 1)`. Good enough for spreading out
+/// retries without pulling in a random number generator.
+fn jitter() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    Instant::now().hash(&mut hasher);
+    thread::current().id().hash(&mut hasher);
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Sleeps for `duration`, waking up periodically to stop early if `cancel` is
+/// cancelled.
+fn sleep(duration: Duration, cancel: Option<&CancelToken>) {
+    const TICK: Duration = Duration::from_millis(100);
+    let started = Instant::now();
+
+    while let Some(remaining) = duration.checked_sub(started.elapsed()) {
+        if remaining.is_zero() || cancel.is_some_and(CancelToken::is_cancelled) {
+            break;
+        }
+        thread::sleep(remaining.min(TICK));
+    }
+}
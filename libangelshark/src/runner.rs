@@ -1,16 +1,43 @@
-use crate::{Acm, Message};
+use crate::{
+    Acm, CancelToken, Executor, Message, OutputRows, Plan, PlannedInput, PlannedJob, RetryPolicy,
+    RunReport, RunnerStream, SessionPool, Timeouts,
+};
 use anyhow::Result;
+use glob::Pattern;
 use rayon::iter::IntoParallelIterator;
-pub use rayon::iter::ParallelIterator;
-use std::collections::HashMap;
+pub use rayon::iter::{IndexedParallelIterator, ParallelIterator};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::{self, Display},
+    io::BufReader,
+    mem,
+    time::Instant,
+};
 
 /// Allows for more convenient running of OSSI [Message]s on one or more [Acm]s,
 /// parallelizing over the ACMs and (optionally) caching results for faster future runs.
 ///
 /// This is the intended high-level use of Angelshark. It holds a collection of
 /// "jobs", which are tagged with ACM names/labels and their associated logins ([Acm]s) and [Message]s).
+///
+/// Jobs run in parallel, but are yielded in the order their first input was
+/// queued, so collecting the results (ex. with [ParallelIterator::collect])
+/// keeps them in input order. Inputs queued for unregistered names are yielded
+/// as [UnknownAcm] errors. Jobs run on rayon's global thread pool unless the
+/// runner has an [Executor].
 #[derive(Default, Debug, Clone)]
-pub struct AcmRunner(HashMap<String, (Acm, Vec<Message>)>);
+pub struct AcmRunner {
+    jobs: HashMap<String, (Acm, Vec<Message>)>,
+    order: Vec<String>,
+    unknown: Vec<(String, Message)>,
+    groups: HashMap<String, Vec<String>>,
+    pool: Option<SessionPool>,
+    timeouts: Option<Timeouts>,
+    cancel: Option<CancelToken>,
+    retry: Option<RetryPolicy>,
+    executor: Option<Executor>,
+}
 
 impl AcmRunner {
     /// Constructs a new [AcmRunner] from tagged [Acm]s and [Message]s.
@@ -25,53 +52,407 @@ impl AcmRunner {
         runner
     }
 
-    /// Registers an [Acm] as `job_name` in the runner.
+    /// Registers an [Acm] as `job_name` in the runner. Registering a name again
+    /// replaces its ACM, but keeps the inputs queued for it. Inputs queued for
+    /// `job_name` before it was registered will run on the new ACM, in their
+    /// place. [crate::Logins] rejects login files that define a name twice.
     pub fn register_acm(&mut self, job_name: &str, acm: Acm) -> &mut Self {
-        self.0.insert(job_name.into(), (acm, Vec::new()));
+        let mut inputs = self
+            .jobs
+            .remove(job_name)
+            .map(|(_, inputs)| inputs)
+            .unwrap_or_default();
+        let (queued, unknown): (Vec<_>, Vec<_>) = mem::take(&mut self.unknown)
+            .into_iter()
+            .partition(|(name, _)| name == job_name);
+        self.unknown = unknown;
+        inputs.extend(queued.into_iter().map(|(_, input)| input));
+        self.jobs.insert(job_name.into(), (acm, inputs));
+        self
+    }
+
+    /// Registers a group of job names as `@name`. Members may be registered
+    /// job names or glob patterns over them (ex. `CM0*`). Groups can't
+    /// contain other groups.
+    pub fn register_group(&mut self, name: &str, members: Vec<String>) -> &mut Self {
+        self.groups.insert(name.into(), members);
+        self
+    }
+
+    /// Runs inputs on logged-in sessions from `pool` instead of logging in
+    /// and off for every run. Manual pages are not affected.
+    pub fn with_pool(&mut self, pool: SessionPool) -> &mut Self {
+        self.pool = Some(pool);
+        self
+    }
+
+    /// Overrides the timeouts of every registered [Acm] when running.
+    pub fn with_timeouts(&mut self, timeouts: Timeouts) -> &mut Self {
+        self.timeouts = Some(timeouts);
+        self
+    }
+
+    /// Stops every run between commands once `cancel` is cancelled. Runs that
+    /// have not connected yet fail right away with [crate::Cancelled].
+    pub fn with_cancel(&mut self, cancel: CancelToken) -> &mut Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    /// Retries runs that fail while logging in to an ACM according to
+    /// `policy`. Runs that fail after their inputs were sent are never
+    /// retried.
+    pub fn with_retry(&mut self, policy: RetryPolicy) -> &mut Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    /// Runs jobs on `executor`, limiting how many run at once overall and per
+    /// ACM. Share one executor between runners to apply the limits across all
+    /// of them.
+    pub fn with_executor(&mut self, executor: Executor) -> &mut Self {
+        self.executor = Some(executor);
         self
     }
 
     /// Queues a [Message] to be run on an [Acm] registered as `job_name`.
+    /// `job_name` may also be a group registered with [Self::register_group]
+    /// (ex. `@east`) or a glob pattern over registered job names (ex. `CM*`),
+    /// which queues the input for every matching job once. Inputs for
+    /// unregistered job names, unknown groups, and patterns that match
+    /// nothing are not run. Instead, running yields an [UnknownAcm] error
+    /// for them, and [Self::plan] lists them.
     pub fn queue_input(&mut self, job_name: &str, input: &Message) -> &mut Self {
-        if let Some((_, inputs)) = self.0.get_mut(job_name) {
-            inputs.push(input.clone());
+        for job_name in self.expand(job_name) {
+            self.queue_job(&job_name, input);
         }
         self
     }
 
+    fn queue_job(&mut self, job_name: &str, input: &Message) {
+        if let Some((_, inputs)) = self.jobs.get_mut(job_name) {
+            if inputs.is_empty() {
+                self.order.push(job_name.into());
+            }
+            inputs.push(input.clone());
+        } else {
+            if !self.unknown.iter().any(|(name, _)| name == job_name) {
+                self.order.push(job_name.into());
+            }
+            self.unknown.push((job_name.into(), input.clone()));
+        }
+    }
+
+    /// Expands a group or glob pattern label into the registered job names
+    /// it stands for, without duplicates, as [Self::queue_input] does.
+    /// Anything that expands to nothing is kept as it is, so that it is
+    /// reported as unknown.
+    pub fn expand(&self, label: &str) -> Vec<String> {
+        let patterns = match label.strip_prefix('@') {
+            Some(group) => match self.groups.get(group) {
+                Some(members) => members.clone(),
+                None => return vec![label.into()],
+            },
+            None => vec![label.into()],
+        };
+
+        let names = self.names();
+        let mut expanded: Vec<String> = Vec::new();
+        for pattern in patterns {
+            let matches = match Pattern::new(&pattern) {
+                Ok(glob) if pattern.contains(['*', '?', '[']) => names
+                    .iter()
+                    .filter(|name| glob.matches(name))
+                    .cloned()
+                    .collect(),
+                _ => vec![pattern],
+            };
+            for name in matches {
+                if !expanded.contains(&name) {
+                    expanded.push(name);
+                }
+            }
+        }
+
+        if expanded.is_empty() {
+            expanded.push(label.into());
+        }
+        expanded
+    }
+
+    /// Returns a [Plan] of what running the queued inputs would do, without
+    /// logging in to any ACMs. Flags inputs for unregistered job names,
+    /// commands that change an ACM, and invalid field IDs.
+    pub fn plan(&self) -> Plan {
+        let jobs = self
+            .order
+            .iter()
+            .filter_map(|job_name| {
+                let (acm, inputs) = self.jobs.get(job_name)?;
+                Some(PlannedJob {
+                    job_name: job_name.clone(),
+                    login: acm.login_key(),
+                    inputs: inputs.iter().map(PlannedInput::new).collect(),
+                })
+            })
+            .collect();
+
+        let names = self.names();
+        let mut unknown = self.unknown.clone();
+        let unknown = self
+            .order
+            .iter()
+            .filter(|job_name| !self.jobs.contains_key(*job_name))
+            .map(|job_name| UnknownAcm::new(job_name, &names, &mut unknown))
+            .collect();
+
+        Plan { jobs, unknown }
+    }
+
     /// Runs the queued [Message] inputs on the registered [Acm]s and returns
     /// the results. The results are returned as an iterator. The iterator must
     /// be in some way consumed, collected, or iterated over before the runner
     /// starts running commands, i.e. it is lazy. Once this begins, results are
-    /// computed in parallel over the ACMs. The order of outputs is undefined.
-    pub fn run(self) -> impl ParallelIterator<Item = RunOutput> {
-        self.0
-            .into_par_iter()
-            .filter(|(_, (_, inputs))| !inputs.is_empty())
-            .map(|(job_name, (acm, inputs))| (job_name, acm.run(&inputs)))
+    /// computed in parallel over the ACMs. Outputs are yielded in input order,
+    /// so collecting them keeps that order, while consuming them with (ex.)
+    /// [ParallelIterator::for_each] handles each one as soon as it finishes.
+    pub fn run(self) -> impl IndexedParallelIterator<Item = RunOutput> {
+        self.run_reports()
+            .map(|(job_name, report)| (job_name, report.into_outputs()))
+    }
+
+    /// Like [Self::run], but yields a [RunReport] for each job, pairing every
+    /// input with its output and recording connect, login, and command times
+    /// and byte counts. Retried jobs only report their last attempt, along
+    /// with the number of attempts.
+    pub fn run_reports(self) -> impl IndexedParallelIterator<Item = ReportOutput> {
+        let (pool, retry, executor) =
+            (self.pool.clone(), self.retry.clone(), self.executor.clone());
+        self.into_jobs().map(move |(job_name, job)| {
+            let (acm, inputs) = match job {
+                Ok(job) => job,
+                Err(unknown) => {
+                    let mut report = RunReport::new(&unknown.inputs);
+                    report.error = Some(unknown.into());
+                    return (job_name, report);
+                }
+            };
+            let started = Instant::now();
+            let mut report = RunReport::new(&inputs);
+            let result = with_retry(retry.as_ref(), &acm, || {
+                report.reset();
+                report.attempts += 1;
+                on_executor(executor.as_ref(), &acm, || match &pool {
+                    Some(pool) => pool.run_into(&acm, &mut report),
+                    None => acm.run_into(&mut report),
+                })
+            });
+            report.error = result.err();
+            report.elapsed = started.elapsed();
+            (job_name, report)
+        })
+    }
+
+    /// Like [Self::run], but returns each ACM's output as a stream of rows
+    /// that can be consumed as they arrive. See [Acm::run_stream].
+    /// When the runner has an [Executor], each stream keeps its ACM's slot
+    /// until it is dropped.
+    pub fn run_stream(self) -> impl IndexedParallelIterator<Item = StreamOutput> {
+        let (retry, executor) = (self.retry.clone(), self.executor.clone());
+        self.into_jobs().map(move |(job_name, job)| {
+            let (acm, inputs) = match job {
+                Ok(job) => job,
+                Err(unknown) => return (job_name, Err(unknown.into())),
+            };
+            let output = with_retry(retry.as_ref(), &acm, || match &executor {
+                Some(executor) => executor.open(&acm, || acm.send_inputs(&inputs)),
+                None => acm.send_inputs(&inputs).map(RunnerStream::new),
+            });
+            (job_name, output.map(Message::stream_rows))
+        })
     }
 
     /// Functionally equivalent to [Self::run] but caches results for 30 minutes
     /// to make future lookups faster.
-    pub fn run_cached(self) -> impl ParallelIterator<Item = RunOutput> {
-        self.0
-            .into_par_iter()
-            .filter(|(_, (_, inputs))| !inputs.is_empty())
-            .map(|(job_name, (acm, inputs))| (job_name, acm.run_cached(&inputs)))
+    pub fn run_cached(self) -> impl IndexedParallelIterator<Item = RunOutput> {
+        let (pool, retry, executor) =
+            (self.pool.clone(), self.retry.clone(), self.executor.clone());
+        self.into_jobs().map(move |(job_name, job)| {
+            let (acm, inputs) = match job {
+                Ok(job) => job,
+                Err(unknown) => return (job_name, Err(unknown.into())),
+            };
+            let output = with_retry(retry.as_ref(), &acm, || {
+                on_executor(executor.as_ref(), &acm, || match &pool {
+                    Some(pool) => pool.run_cached(&acm, &inputs),
+                    None => acm.run_cached(&inputs),
+                })
+            });
+            (job_name, output)
+        })
     }
 
     /// Functionally equivalent to [Self::run] but returns manual pages for
     /// inputs instead of executing them.
-    pub fn manuals(self) -> impl ParallelIterator<Item = ManualOutput> {
-        self.0
-            .into_par_iter()
-            .filter(|(_, (_, inputs))| !inputs.is_empty())
-            .map(|(job_name, (acm, inputs))| (job_name, acm.manual(&inputs)))
+    pub fn manuals(self) -> impl IndexedParallelIterator<Item = ManualOutput> {
+        let (retry, executor) = (self.retry.clone(), self.executor.clone());
+        self.into_jobs().map(move |(job_name, job)| {
+            let (acm, inputs) = match job {
+                Ok(job) => job,
+                Err(unknown) => return (job_name, Err(unknown.into())),
+            };
+            let output = with_retry(retry.as_ref(), &acm, || {
+                on_executor(executor.as_ref(), &acm, || acm.manual(&inputs))
+            });
+            (job_name, output)
+        })
+    }
+
+    /// Registered job names, sorted.
+    fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.jobs.keys().cloned().collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Takes the jobs with queued inputs in input order, applying the runner's timeouts and
+    /// cancellation to their ACMs. Inputs for unregistered job names are
+    /// gathered into an [UnknownAcm] per name.
+    fn into_jobs(self) -> impl IndexedParallelIterator<Item = (String, Job)> {
+        let names = self.names();
+        let (timeouts, cancel) = (self.timeouts, self.cancel);
+        let (mut jobs, mut unknown) = (self.jobs, self.unknown);
+
+        let jobs: Vec<_> = self
+            .order
+            .into_iter()
+            .map(|job_name| {
+                let job = match jobs.remove(&job_name) {
+                    Some(job) => Ok(job),
+                    None => Err(UnknownAcm::new(&job_name, &names, &mut unknown)),
+                };
+                (job_name, job)
+            })
+            .collect();
+
+        jobs.into_par_iter().map(move |(job_name, job)| {
+            let job = job.map(|(mut acm, inputs)| {
+                if let Some(timeouts) = timeouts {
+                    acm.with_timeouts(timeouts);
+                }
+                if let Some(cancel) = &cancel {
+                    acm.with_cancel(cancel.clone());
+                }
+                (acm, inputs)
+            });
+            (job_name, job)
+        })
+    }
+}
+
+/// A job's registered [Acm] and queued inputs, or its inputs if no ACM is
+/// registered under its name.
+type Job = Result<(Acm, Vec<Message>), UnknownAcm>;
+
+/// The error yielded for a job name that has no registered [Acm]. Carries the
+/// inputs that were queued for it, which were not run.
+#[derive(Debug, Clone)]
+pub struct UnknownAcm {
+    pub job_name: String,
+    /// The most similar registered job name, if any is close.
+    pub suggestion: Option<String>,
+    pub inputs: Vec<Message>,
+}
+
+impl UnknownAcm {
+    /// Takes the inputs for `job_name` out of `unknown`, suggesting one of
+    /// `names` instead.
+    fn new(job_name: &str, names: &[String], unknown: &mut Vec<(String, Message)>) -> Self {
+        let (inputs, rest) = unknown.drain(..).partition(|(name, _)| name == job_name);
+        *unknown = rest;
+
+        Self {
+            job_name: job_name.into(),
+            suggestion: suggest(job_name, names),
+            inputs: inputs.into_iter().map(|(_, input)| input).collect(),
+        }
+    }
+}
+
+impl Display for UnknownAcm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "No ACM is configured as '{}'.", self.job_name)?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, " Did you mean '{}'?", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for UnknownAcm {}
+
+/// Returns the name in `names` closest to `name` (ignoring case), if it is
+/// within a few edits.
+fn suggest(name: &str, names: &[String]) -> Option<String> {
+    let name = name.to_lowercase();
+    names
+        .iter()
+        .map(|candidate| (edit_distance(&name, &candidate.to_lowercase()), candidate))
+        .filter(|(distance, _)| *distance <= (name.chars().count() / 3).max(2))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate.clone())
+}
+
+/// The Levenshtein distance between `a` and `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+/// Calls `run` for `acm`, retrying it according to `policy` if there is one.
+fn with_retry<T>(
+    policy: Option<&RetryPolicy>,
+    acm: &Acm,
+    mut run: impl FnMut() -> Result<T>,
+) -> Result<T> {
+    match policy {
+        Some(policy) => policy.run(acm.cancel_token(), run),
+        None => run(),
+    }
+}
+
+/// Calls `run` for `acm` on `executor` if there is one, or on the current
+/// thread otherwise.
+fn on_executor<T: Send>(
+    executor: Option<&Executor>,
+    acm: &Acm,
+    run: impl FnOnce() -> Result<T> + Send,
+) -> Result<T> {
+    match executor {
+        Some(executor) => executor.run(acm, run),
+        None => run(),
     }
 }
 
 /// Every resulting entry of [AcmRunner::run]
 pub type RunOutput = (String, Result<Vec<Message>>);
 
+/// Every resulting entry of [AcmRunner::run_reports]
+pub type ReportOutput = (String, RunReport);
+
+/// Every resulting entry of [AcmRunner::run_stream]
+pub type StreamOutput = (String, Result<OutputRows<BufReader<RunnerStream>>>);
+
 /// Every resulting entry of [AcmRunner::manuals]
 pub type ManualOutput = (String, Result<String>);
@@ -0,0 +1,111 @@
+use anyhow::{anyhow, Context, Result};
+use std::{
+    env, fs,
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::Arc,
+};
+
+/// A source of a secret, such as an ACM password, that is looked up every
+/// time an [crate::Acm] connects instead of being kept in its configuration.
+/// See [crate::Acm::with_pass_provider].
+pub trait SecretProvider: Send + Sync {
+    /// Returns the current secret.
+    fn secret(&self) -> Result<String>;
+}
+
+/// Reads a secret from the environment variable `name`.
+#[derive(Debug, Clone)]
+pub struct EnvSecret {
+    pub name: String,
+}
+
+/// Reads a secret from the file at `path` (ex. a mounted container secret).
+/// A trailing newline is not part of the secret.
+#[derive(Debug, Clone)]
+pub struct FileSecret {
+    pub path: PathBuf,
+}
+
+/// Runs `command` with the system shell and reads a secret from its standard
+/// output (ex. a password manager's CLI). A trailing newline is not part of
+/// the secret. Fails if the command does.
+#[derive(Debug, Clone)]
+pub struct CommandSecret {
+    pub command: String,
+}
+
+impl SecretProvider for EnvSecret {
+    fn secret(&self) -> Result<String> {
+        env::var(&self.name)
+            .with_context(|| format!("Failed to read secret from environment: {}", self.name))
+    }
+}
+
+impl SecretProvider for FileSecret {
+    fn secret(&self) -> Result<String> {
+        let secret = fs::read_to_string(&self.path)
+            .with_context(|| format!("Failed to read secret from file: {}", self.path.display()))?;
+        Ok(trim_newline(secret))
+    }
+}
+
+impl SecretProvider for CommandSecret {
+    fn secret(&self) -> Result<String> {
+        let (shell, flag) = if cfg!(windows) {
+            ("cmd", "/C")
+        } else {
+            ("sh", "-c")
+        };
+        let output = Command::new(shell)
+            .args([flag, &self.command])
+            .stdin(Stdio::null())
+            .stderr(Stdio::inherit())
+            .output()
+            .with_context(|| format!("Failed to run secret command: {}", self.command))?;
+
+        if !output.status.success() {
+            return Err(anyhow!(
+                "Secret command failed with {}: {}",
+                output.status,
+                self.command
+            ));
+        }
+        let secret = String::from_utf8(output.stdout)
+            .with_context(|| format!("Secret command printed invalid UTF-8: {}", self.command))?;
+        Ok(trim_newline(secret))
+    }
+}
+
+/// An ACM password, either kept in memory or looked up when needed.
+#[derive(Clone)]
+pub(crate) enum Password {
+    Plain(String),
+    Provider(Arc<dyn SecretProvider>),
+}
+
+impl Default for Password {
+    fn default() -> Self {
+        Self::Plain(String::new())
+    }
+}
+
+impl Password {
+    /// Whether there is a password to try at all.
+    pub(crate) fn is_set(&self) -> bool {
+        !matches!(self, Self::Plain(pass) if pass.is_empty())
+    }
+
+    pub(crate) fn resolve(&self) -> Result<String> {
+        match self {
+            Self::Plain(pass) => Ok(pass.clone()),
+            Self::Provider(provider) => provider.secret(),
+        }
+    }
+}
+
+fn trim_newline(mut secret: String) -> String {
+    let len = secret.trim_end_matches(['\r', '\n']).len();
+    secret.truncate(len);
+    secret
+}
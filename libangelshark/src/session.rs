@@ -0,0 +1,142 @@
+use crate::{
+    acm::{OSSI_LOGOFF, OSSI_TERM},
+    timeout::{as_millis, Deadline},
+    Acm, CancelToken, Message, Phase, RunReport,
+};
+use anyhow::{anyhow, Context, Result};
+use ssh2::{Channel, Session};
+use std::{
+    io::{self, BufReader, Read, Write},
+    time::{Duration, Instant},
+};
+
+/// A logged-in SSH session with an open OSSI terminal.
+pub(crate) struct OssiSession {
+    session: Session,
+    channel: Channel,
+    logoff_limit: Duration,
+    pub(crate) last_used: Instant,
+}
+
+impl OssiSession {
+    /// Logs in to the ACM and opens an OSSI terminal, recording how long that
+    /// took in `report`.
+    pub(crate) fn open(acm: &Acm, deadline: &Deadline, report: &mut RunReport) -> Result<Self> {
+        let (session, channel) = acm.open_channel(OSSI_TERM, deadline, report)?;
+        Ok(Self {
+            session,
+            channel,
+            logoff_limit: acm.timeouts().command,
+            last_used: Instant::now(),
+        })
+    }
+
+    /// Sends each input of `report` in turn, reading its output up to the OSSI
+    /// terminator before sending the next, and records the outputs in
+    /// `report`. Each command must finish within `command` (and the run's
+    /// `deadline`). If `cancel` is cancelled, stops before sending the next
+    /// [Message].
+    pub(crate) fn run(
+        &mut self,
+        report: &mut RunReport,
+        command: Duration,
+        deadline: &Deadline,
+        cancel: Option<&CancelToken>,
+    ) -> Result<()> {
+        for entry in &mut report.commands {
+            CancelToken::check(cancel)?;
+            let (limit, timeout) = deadline.limit(Phase::Command, command)?;
+            let started = Instant::now();
+            let output = timeout.attach(self.run_one(
+                &entry.input,
+                limit,
+                &mut entry.bytes_sent,
+                &mut entry.bytes_received,
+            ));
+            entry.elapsed = started.elapsed();
+            entry.output = Some(output?);
+            self.last_used = Instant::now();
+        }
+
+        Ok(())
+    }
+
+    /// Sends one [Message] and reads its output, failing if that takes longer
+    /// than `limit`. Counts the bytes written and read in `sent` and
+    /// `received`.
+    fn run_one(
+        &mut self,
+        input: &Message,
+        limit: Duration,
+        sent: &mut usize,
+        received: &mut usize,
+    ) -> Result<Message> {
+        let started = Instant::now();
+        self.session.set_timeout(as_millis(limit));
+
+        let input = input.to_string();
+        self.channel
+            .write_all(input.as_bytes())
+            .with_context(|| "Failed to write input to OSSI stream.")?;
+        self.channel
+            .flush()
+            .with_context(|| "Failed to flush OSSI stream.")?;
+        *sent = input.len();
+
+        let mut reader = BufReader::new(TimedReader {
+            session: &self.session,
+            channel: &mut self.channel,
+            started,
+            limit,
+            received,
+        });
+        Message::read_output(&mut reader)?
+            .ok_or_else(|| anyhow!("OSSI stream closed before output was terminated."))
+    }
+
+    /// Checks that the channel is still open and the connection is alive by
+    /// reading without blocking. An idle session has nothing to read, so
+    /// anything but [io::ErrorKind::WouldBlock] means the ACM has closed the
+    /// connection (or sent output that no command asked for).
+    pub(crate) fn is_healthy(&mut self) -> bool {
+        self.session.set_blocking(false);
+        let probe = self.channel.read(&mut [0; 1]);
+        self.session.set_blocking(true);
+        matches!(probe, Err(e) if e.kind() == io::ErrorKind::WouldBlock) && !self.channel.eof()
+    }
+}
+
+impl Drop for OssiSession {
+    /// Logs off of the ACM, waiting for it to close the channel. Errors are
+    /// ignored, since the session may already be dead.
+    fn drop(&mut self) {
+        self.session.set_timeout(as_millis(self.logoff_limit));
+        if self.channel.write_all(OSSI_LOGOFF).is_ok() {
+            let _ = io::copy(&mut self.channel, &mut io::sink());
+        }
+        let _ = self.channel.send_eof();
+    }
+}
+
+/// Reads from an OSSI channel, shortening the session timeout before each read
+/// so that a command's output as a whole must arrive within `limit`.
+struct TimedReader<'a> {
+    session: &'a Session,
+    channel: &'a mut Channel,
+    started: Instant,
+    limit: Duration,
+    received: &'a mut usize,
+}
+
+impl Read for TimedReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.limit.saturating_sub(self.started.elapsed());
+        if remaining.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        self.session.set_timeout(as_millis(remaining));
+        let read = self.channel.read(buf)?;
+        *self.received += read;
+        Ok(read)
+    }
+}
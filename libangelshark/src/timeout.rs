@@ -0,0 +1,231 @@
+use anyhow::Result;
+use std::{
+    error::Error,
+    fmt::{Display, Formatter, Result as FmtResult},
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+const LIBSSH2_ERROR_TIMEOUT: i32 = -9;
+
+/// How often a run waiting for a busy ACM or session checks whether it was
+/// cancelled.
+pub(crate) const CANCEL_POLL: Duration = Duration::from_millis(100);
+
+/// Time limits for each phase of running [crate::Message]s on an
+/// [crate::Acm]. The connect, login, and command limits default to thirty
+/// seconds. The overall run has no limit by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Limit for opening a TCP connection to each of the ACM's addresses.
+    pub connect: Duration,
+    /// Limit for the SSH handshake, authentication, and reaching the OSSI
+    /// terminal.
+    pub login: Duration,
+    /// Limit for each command to return all of its output.
+    pub command: Duration,
+    /// Limit for an entire run, from connecting to the last command.
+    pub run: Option<Duration>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            connect: DEFAULT_TIMEOUT,
+            login: DEFAULT_TIMEOUT,
+            command: DEFAULT_TIMEOUT,
+            run: None,
+        }
+    }
+}
+
+/// Some of the limits in [Timeouts], such as those set for one ACM in a logins
+/// file. The rest come from whatever defaults apply to that ACM.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct TimeoutOverrides {
+    pub(crate) connect: Option<Duration>,
+    pub(crate) login: Option<Duration>,
+    pub(crate) command: Option<Duration>,
+    pub(crate) run: Option<Duration>,
+}
+
+impl TimeoutOverrides {
+    /// Takes each limit set here, and the rest from `defaults`.
+    pub(crate) fn or(self, defaults: Timeouts) -> Timeouts {
+        Timeouts {
+            connect: self.connect.unwrap_or(defaults.connect),
+            login: self.login.unwrap_or(defaults.login),
+            command: self.command.unwrap_or(defaults.command),
+            run: self.run.or(defaults.run),
+        }
+    }
+}
+
+/// A phase of running [crate::Message]s on an [crate::Acm].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Connect,
+    Login,
+    Command,
+    Run,
+}
+
+impl Display for Phase {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(match self {
+            Self::Connect => "connect",
+            Self::Login => "login",
+            Self::Command => "command",
+            Self::Run => "run",
+        })
+    }
+}
+
+/// Returned (wrapped in an [anyhow::Error]) when a phase of a run exceeds its
+/// limit in [Timeouts]. Callers can detect it with
+/// [anyhow::Error::downcast_ref].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimedOut {
+    pub phase: Phase,
+    pub limit: Duration,
+}
+
+impl Display for TimedOut {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(
+            f,
+            "Timed out during {} after {:.1} seconds.",
+            self.phase,
+            self.limit.as_secs_f32()
+        )
+    }
+}
+
+impl Error for TimedOut {}
+
+impl TimedOut {
+    /// Marks the error in `result` with this timeout if it was caused by a
+    /// socket or SSH session timeout.
+    pub(crate) fn attach<T>(self, result: Result<T>) -> Result<T> {
+        result.map_err(|e| if is_timeout(&e) { e.context(self) } else { e })
+    }
+}
+
+/// Returned (wrapped in an [anyhow::Error]) when a run is stopped by its
+/// [CancelToken].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl Display for Cancelled {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str("Run was cancelled.")
+    }
+}
+
+impl Error for Cancelled {}
+
+/// Stops runs in progress. Runs check the token before connecting and between
+/// messages, so a cancelled run finishes its current command, logs off, and
+/// returns [Cancelled]. Clones share the same cancellation state.
+#[derive(Debug, Default, Clone)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    /// Cancels every run using this token (or its clones).
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether [Self::cancel] has been called.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Returns [Cancelled] if the token has been cancelled.
+    pub(crate) fn check(token: Option<&Self>) -> Result<()> {
+        match token {
+            Some(token) if token.is_cancelled() => Err(Cancelled.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Tracks the time left in a run so that phase limits never exceed the overall
+/// run limit.
+pub(crate) struct Deadline {
+    started: Instant,
+    run: Option<Duration>,
+}
+
+impl Deadline {
+    pub(crate) fn new(timeouts: &Timeouts) -> Self {
+        Self {
+            started: Instant::now(),
+            run: timeouts.run,
+        }
+    }
+
+    /// Returns how long the next `phase` may take: the smaller of its own
+    /// `limit` and the time left in the run. Also returns the error to report
+    /// if that time runs out. Fails if the run is already out of time.
+    pub(crate) fn limit(&self, phase: Phase, limit: Duration) -> Result<(Duration, TimedOut)> {
+        let phase_timeout = TimedOut { phase, limit };
+
+        match self.run {
+            Some(run) => {
+                let run_timeout = TimedOut {
+                    phase: Phase::Run,
+                    limit: run,
+                };
+                let remaining = run.saturating_sub(self.started.elapsed());
+
+                if remaining.is_zero() {
+                    Err(run_timeout.into())
+                } else if remaining < limit {
+                    Ok((remaining, run_timeout))
+                } else {
+                    Ok((limit, phase_timeout))
+                }
+            }
+            None => Ok((limit, phase_timeout)),
+        }
+    }
+
+    /// Returns the time left in the run, or `None` if it has no limit. Fails
+    /// if the run is already out of time.
+    pub(crate) fn remaining(&self) -> Result<Option<Duration>> {
+        match self.run {
+            Some(run) => self
+                .limit(Phase::Run, run)
+                .map(|(remaining, _)| Some(remaining)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Whether `error` was caused by a socket or SSH session timeout.
+fn is_timeout(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        if let Some(e) = cause.downcast_ref::<ssh2::Error>() {
+            e.code() == ssh2::ErrorCode::Session(LIBSSH2_ERROR_TIMEOUT)
+        } else if let Some(e) = cause.downcast_ref::<io::Error>() {
+            matches!(
+                e.kind(),
+                io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+            )
+        } else {
+            false
+        }
+    })
+}
+
+/// Converts `limit` to milliseconds for [ssh2::Session::set_timeout]. Never
+/// returns zero, which would disable the timeout.
+pub(crate) fn as_millis(limit: Duration) -> u32 {
+    limit.as_millis().clamp(1, u32::MAX as u128) as u32
+}
@@ -0,0 +1,686 @@
+use angelsharkmock::{MockAcm, MockServer, Reply, TempFile};
+use libangelshark::{
+    Acm, AcmRunner, CancelToken, Cancelled, CommandSecret, ConnectFailure, Executor, FileSecret,
+    HostKeyCheck, HostKeyMismatch, Logins, Message, OutputRow, ParallelIterator, RetryPolicy,
+    SessionPool, TimedOut, Timeouts, UnknownAcm,
+};
+use std::{
+    fs,
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// The public key of tests/keys/id_ed25519.
+const AUTHORIZED_KEY: &str = include_str!("keys/id_ed25519.pub");
+
+fn acm(server: &MockServer) -> Acm {
+    let mut acm = Acm::default();
+    acm.with_addr(server.addr().ip())
+        .with_port(server.addr().port())
+        .with_user("mock")
+        .with_pass("mock")
+        .with_host_key_check(HostKeyCheck::Disabled);
+    acm
+}
+
+/// Like [acm], but logs in with the private key `key` in tests/keys instead
+/// of a password.
+fn key_acm(server: &MockServer, key: &str) -> Acm {
+    let mut acm = Acm::default();
+    acm.with_addr(server.addr().ip())
+        .with_port(server.addr().port())
+        .with_user("mock")
+        .with_key_file(format!("{}/tests/keys/{}", env!("CARGO_MANIFEST_DIR"), key))
+        .with_host_key_check(HostKeyCheck::Disabled);
+    acm
+}
+
+/// Like [acm], but verifies the host key against `known_hosts` with `check`.
+fn checked_acm(server: &MockServer, check: HostKeyCheck, known_hosts: &TempFile) -> Acm {
+    let mut acm = acm(server);
+    acm.with_host_key_check(check)
+        .with_known_hosts(known_hosts.path());
+    acm
+}
+
+fn message(command: &str, fields: &[&str]) -> Message {
+    let mut message = Message::new(command);
+    if !fields.is_empty() {
+        message.fields = Some(fields.iter().map(|f| f.to_string()).collect());
+    }
+    message
+}
+
+#[test]
+fn run_returns_data_and_errors() {
+    let server = MockAcm::stations().start().unwrap();
+    let outputs = acm(&server)
+        .run(&[
+            message("list station", &["8003ff00"]),
+            message("display station 17571239999", &[]),
+        ])
+        .unwrap();
+
+    assert_eq!(outputs[0].fields, Some(vec!["8003ff00".into()]));
+    assert_eq!(
+        outputs[0].datas,
+        Some(vec![
+            vec!["Carpenter, Adam".into()],
+            vec!["Doe, Jane".into()]
+        ])
+    );
+    assert!(outputs[1].ossi_error().unwrap().is_not_found());
+    assert_eq!(server.inputs().len(), 2);
+}
+
+#[test]
+fn run_stream_yields_rows() {
+    let server = MockAcm::stations().start().unwrap();
+    let rows: Vec<OutputRow> = acm(&server)
+        .run_stream(&[message("list station", &[])])
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+
+    assert_eq!(
+        rows,
+        vec![
+            OutputRow::Command("list station".into()),
+            OutputRow::Fields(vec!["8005ff00".into(), "8003ff00".into()]),
+            OutputRow::Data(vec!["17571230000".into(), "Carpenter, Adam".into()]),
+            OutputRow::Data(vec!["17571230001".into(), "Doe, Jane".into()]),
+            OutputRow::End,
+        ]
+    );
+}
+
+#[test]
+fn manual_returns_page() {
+    let server = MockAcm::stations()
+        .with_manual("list station", "clist station\nf8005ff00\nt")
+        .start()
+        .unwrap();
+    let page = acm(&server)
+        .manual(&[message("list station", &[])])
+        .unwrap();
+
+    assert!(page.starts_with("clist station\nf8005ff00\nt\n"));
+}
+
+#[test]
+fn wrong_password_fails() {
+    let server = MockAcm::stations().start().unwrap();
+    let error = acm(&server)
+        .with_pass("wrong")
+        .run(&[message("list station", &[])])
+        .unwrap_err();
+
+    assert!(ConnectFailure::of(&error).is_none());
+    assert_eq!(server.logins(), 0);
+}
+
+#[test]
+fn key_logins_need_no_password() {
+    let server = MockAcm::stations()
+        .with_authorized_key(AUTHORIZED_KEY)
+        .start()
+        .unwrap();
+
+    assert!(key_acm(&server, "id_ed25519")
+        .run(&[message("list station", &[])])
+        .is_ok());
+    assert!(key_acm(&server, "id_ed25519_other")
+        .run(&[message("list station", &[])])
+        .is_err());
+    assert_eq!(server.logins(), 1);
+}
+
+#[test]
+fn key_passphrases_decrypt_keys() {
+    let server = MockAcm::stations()
+        .with_authorized_key(include_str!("keys/id_ed25519_secret.pub"))
+        .start()
+        .unwrap();
+    let key = include_str!("keys/id_ed25519_secret");
+    let inputs = [message("list station", &[])];
+
+    let mut acm = key_acm(&server, "id_ed25519_secret");
+    assert!(acm
+        .with_key(key)
+        .with_key_pass("secret")
+        .run(&inputs)
+        .is_ok());
+    assert!(acm.with_key_pass("wrong").run(&inputs).is_err());
+    assert_eq!(server.logins(), 1);
+}
+
+#[test]
+fn rejected_keys_fall_back_to_password() {
+    let server = MockAcm::stations()
+        .with_authorized_key(AUTHORIZED_KEY)
+        .start()
+        .unwrap();
+
+    let outputs = acm(&server)
+        .with_key_file(format!(
+            "{}/tests/keys/id_ed25519_other",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .run(&[message("list station", &[])]);
+
+    assert!(outputs.is_ok());
+    assert_eq!(server.logins(), 1);
+}
+
+#[test]
+fn strict_host_key_check_refuses_unknown_hosts() {
+    let server = MockAcm::stations().start().unwrap();
+    let known_hosts = TempFile::new("known_hosts", "").unwrap();
+
+    let error = checked_acm(&server, HostKeyCheck::Strict, &known_hosts)
+        .run(&[message("list station", &[])])
+        .unwrap_err();
+
+    assert!(format!("{:#}", error).contains("strict host key checking is enabled"));
+    assert_eq!(fs::read_to_string(known_hosts.path()).unwrap(), "");
+    assert_eq!(server.logins(), 0);
+}
+
+#[test]
+fn first_use_records_host_keys() {
+    let server = MockAcm::stations().start().unwrap();
+    let known_hosts = TempFile::new("known_hosts", "").unwrap();
+    let inputs = [message("list station", &[])];
+    let tofu = checked_acm(&server, HostKeyCheck::TrustOnFirstUse, &known_hosts);
+
+    assert!(tofu.run(&inputs).is_ok());
+    let recorded = fs::read_to_string(known_hosts.path()).unwrap();
+    assert!(recorded.starts_with(&format!(
+        "[127.0.0.1]:{} ssh-ed25519 ",
+        server.addr().port()
+    )));
+
+    // Once recorded, the host is known, even to strict checks.
+    assert!(tofu.run(&inputs).is_ok());
+    assert!(checked_acm(&server, HostKeyCheck::Strict, &known_hosts)
+        .run(&inputs)
+        .is_ok());
+    assert_eq!(fs::read_to_string(known_hosts.path()).unwrap(), recorded);
+    assert_eq!(server.logins(), 3);
+}
+
+#[test]
+fn changed_host_keys_are_refused() {
+    let server = MockAcm::stations().start().unwrap();
+    let known_hosts = TempFile::new("known_hosts", "").unwrap();
+    let inputs = [message("list station", &[])];
+    assert!(
+        checked_acm(&server, HostKeyCheck::TrustOnFirstUse, &known_hosts)
+            .run(&inputs)
+            .is_ok()
+    );
+
+    // Record the first ACM's key for a rebuilt one's address.
+    let rebuilt = MockAcm::stations()
+        .with_host_key(*b"angelshark rebuilt acm host key!")
+        .start()
+        .unwrap();
+    let recorded = fs::read_to_string(known_hosts.path()).unwrap().replace(
+        &format!("[127.0.0.1]:{}", server.addr().port()),
+        &format!("[127.0.0.1]:{}", rebuilt.addr().port()),
+    );
+    fs::write(known_hosts.path(), &recorded).unwrap();
+
+    let error = checked_acm(&rebuilt, HostKeyCheck::TrustOnFirstUse, &known_hosts)
+        .run(&inputs)
+        .unwrap_err();
+
+    let mismatch = error.downcast_ref::<HostKeyMismatch>().unwrap();
+    // As `ssh-keygen -lf` prints it for the rebuilt ACM's key.
+    assert_eq!(
+        mismatch.fingerprint,
+        "MJagOIvAaxRm4DKRrl6yxLYBCHOENrjr8cGZ6l8wsJ8"
+    );
+    assert_eq!(fs::read_to_string(known_hosts.path()).unwrap(), recorded);
+    assert_eq!(rebuilt.logins(), 0);
+}
+
+#[test]
+fn pool_reuses_sessions() {
+    let server = MockAcm::stations().start().unwrap();
+    let pool = SessionPool::new(1, Duration::from_secs(60));
+    let acm = acm(&server);
+
+    for _ in 0..3 {
+        pool.run(&acm, &[message("list station", &[])]).unwrap();
+    }
+
+    assert_eq!(server.logins(), 1);
+    assert_eq!(server.inputs().len(), 3);
+}
+
+#[test]
+fn pool_replaces_dropped_sessions() {
+    let server = MockAcm::stations().start().unwrap();
+    let pool = SessionPool::new(1, Duration::from_secs(60));
+    let acm = acm(&server);
+
+    pool.run(&acm, &[message("list station", &[])]).unwrap();
+    server.disconnect();
+    let started = Instant::now();
+    while server.sessions() > 0 && started.elapsed() < Duration::from_secs(5) {
+        thread::sleep(Duration::from_millis(10));
+    }
+    pool.run(&acm, &[message("list station", &[])]).unwrap();
+
+    assert_eq!(server.logins(), 2);
+}
+
+#[test]
+fn pool_wakes_waiters_for_every_login() {
+    let slow = || {
+        MockAcm::stations()
+            .with_reply(
+                "list station",
+                Reply::ok().delayed(Duration::from_millis(100)),
+            )
+            .start()
+            .unwrap()
+    };
+    let servers = [slow(), slow()];
+    let pool = SessionPool::new(1, Duration::from_secs(60));
+    let (done, finished) = mpsc::channel();
+
+    // Both logins have runs waiting for their only session at once.
+    for server in &servers {
+        for _ in 0..3 {
+            let (pool, acm, done) = (pool.clone(), acm(server), done.clone());
+            thread::spawn(move || {
+                let _ = done.send(pool.run(&acm, &[message("list station", &[])]).is_ok());
+            });
+        }
+    }
+
+    for _ in 0..6 {
+        assert!(finished.recv_timeout(Duration::from_secs(10)).unwrap());
+    }
+    assert_eq!(servers[0].logins(), 1);
+    assert_eq!(servers[1].logins(), 1);
+}
+
+#[test]
+fn pool_waits_can_be_cancelled() {
+    let server = MockAcm::stations()
+        .with_reply("list station", Reply::ok().delayed(Duration::from_secs(2)))
+        .start()
+        .unwrap();
+    let pool = SessionPool::new(1, Duration::from_secs(60));
+    let busy = {
+        let (pool, acm) = (pool.clone(), acm(&server));
+        thread::spawn(move || pool.run(&acm, &[message("list station", &[])]))
+    };
+    thread::sleep(Duration::from_millis(200));
+
+    let cancel = CancelToken::default();
+    let mut waiting = acm(&server);
+    waiting.with_cancel(cancel.clone());
+    let canceller = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        cancel.cancel();
+    });
+    let started = Instant::now();
+    let error = pool
+        .run(&waiting, &[message("list station", &[])])
+        .unwrap_err();
+
+    assert!(error.is::<Cancelled>());
+    assert!(started.elapsed() < Duration::from_secs(1));
+    canceller.join().unwrap();
+    assert!(busy.join().unwrap().is_ok());
+}
+
+#[test]
+fn cache_is_keyed_by_login() {
+    let server = MockAcm::stations().start().unwrap();
+    let inputs = [message("list station", &[])];
+    acm(&server).run_cached(&inputs).unwrap();
+
+    let mut intruder = acm(&server);
+    intruder.with_user("intruder");
+
+    assert!(intruder.run_cached(&inputs).is_err());
+}
+
+#[test]
+fn slow_command_times_out() {
+    let server = MockAcm::stations()
+        .with_reply("list station", Reply::ok().delayed(Duration::from_secs(5)))
+        .start()
+        .unwrap();
+    let error = acm(&server)
+        .with_timeouts(Timeouts {
+            command: Duration::from_millis(300),
+            ..Default::default()
+        })
+        .run(&[message("list station", &[])])
+        .unwrap_err();
+
+    let timed_out = error.downcast_ref::<TimedOut>().unwrap();
+    assert_eq!(timed_out.limit, Duration::from_millis(300));
+}
+
+#[test]
+fn login_file_timeouts_keep_other_defaults() {
+    let server = MockAcm::stations()
+        .with_reply("list station", Reply::ok().delayed(Duration::from_secs(5)))
+        .start()
+        .unwrap();
+    let mut logins = Logins::from_toml(&format!(
+        "[acms.CM01]\nhost = \"{}\"\nport = {}\nuser = \"mock\"\npass = \"mock\"\nhostkey = \"off\"\ntimeouts = {{ login = 90 }}\n",
+        server.addr().ip(),
+        server.addr().port()
+    ))
+    .unwrap();
+    let (_, acm) = &mut logins.acms[0];
+    let error = acm
+        .with_timeout_defaults(Timeouts {
+            command: Duration::from_millis(300),
+            ..Default::default()
+        })
+        .run(&[message("list station", &[])])
+        .unwrap_err();
+
+    let timed_out = error.downcast_ref::<TimedOut>().unwrap();
+    assert_eq!(timed_out.limit, Duration::from_millis(300));
+}
+
+#[test]
+fn runner_retries_exhausted_logins_only() {
+    let server = MockAcm::stations().with_max_logins(0).start().unwrap();
+    let mut runner = AcmRunner::new(
+        vec![("CM01".into(), acm(&server))],
+        vec![("CM01".into(), message("change station 17571230000", &[]))],
+    );
+    runner.with_retry(RetryPolicy {
+        max_attempts: 2,
+        initial_backoff: Duration::from_millis(10),
+        ..Default::default()
+    });
+
+    let outputs: Vec<_> = runner.run().collect();
+    let error = outputs[0].1.as_ref().unwrap_err();
+
+    assert_eq!(ConnectFailure::of(error), Some(ConnectFailure::Prompt));
+    assert_eq!(server.logins(), 2);
+    assert!(server.inputs().is_empty());
+}
+
+#[test]
+fn runner_yields_jobs_in_input_order() {
+    let servers: Vec<MockServer> = (0..4)
+        .map(|_| MockAcm::stations().start().unwrap())
+        .collect();
+    let names = ["CM03", "CM01", "CM04", "CM02"];
+    let mut runner = AcmRunner::default();
+    for (name, server) in names.iter().zip(&servers) {
+        runner.register_acm(name, acm(server));
+    }
+    for name in names.iter().rev() {
+        runner.queue_input(name, &message("list station", &[]));
+    }
+
+    let outputs: Vec<String> = runner.run().map(|(name, _)| name).collect();
+
+    assert_eq!(outputs, ["CM02", "CM04", "CM01", "CM03"]);
+}
+
+#[test]
+fn executor_limits_logins_per_acm() {
+    let server = MockAcm::stations()
+        .with_max_logins(1)
+        .with_reply(
+            "list station",
+            Reply::ok().delayed(Duration::from_millis(200)),
+        )
+        .start()
+        .unwrap();
+    let mut runner = AcmRunner::default();
+    for name in ["CM01", "CM02", "CM03"] {
+        runner
+            .register_acm(name, acm(&server))
+            .queue_input(name, &message("list station", &[]));
+    }
+    runner.with_executor(Executor::new(4, 1).unwrap());
+
+    let outputs: Vec<_> = runner.run().collect();
+
+    assert!(outputs.iter().all(|(_, output)| output.is_ok()));
+    assert_eq!(server.logins(), 3);
+}
+
+#[test]
+fn executor_runs_other_acms_while_one_is_busy() {
+    let busy = MockAcm::stations()
+        .with_reply(
+            "list station",
+            Reply::ok().delayed(Duration::from_millis(500)),
+        )
+        .start()
+        .unwrap();
+    let idle = MockAcm::stations().start().unwrap();
+    let mut runner = AcmRunner::default();
+    for name in ["CM01", "CM02", "CM03", "CM04"] {
+        runner
+            .register_acm(name, acm(&busy))
+            .queue_input(name, &message("list station", &[]));
+    }
+    runner
+        .register_acm("CM05", acm(&idle))
+        .queue_input("CM05", &message("list station", &[]));
+    runner.with_executor(Executor::new(2, 1).unwrap());
+    let started = Instant::now();
+
+    let finished: Vec<_> = runner
+        .run()
+        .map(|(name, output)| (name, output.is_ok(), started.elapsed()))
+        .collect();
+
+    assert!(finished.iter().all(|(_, ok, _)| *ok));
+    assert_eq!(finished[4].0, "CM05");
+    assert!(finished[4].2 < Duration::from_millis(500));
+    assert_eq!(busy.logins(), 4);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn run_async_does_not_block_runtime() {
+    let server = MockAcm::stations()
+        .with_reply(
+            "list station",
+            Reply::ok().delayed(Duration::from_millis(300)),
+        )
+        .start()
+        .unwrap();
+    let acm = acm(&server);
+    let inputs = [message("list station", &[])];
+    let started = Instant::now();
+
+    // The test runtime has a single thread, so the second future only gets to
+    // run early if the first isn't blocking it.
+    let (outputs, ticked) = tokio::join!(acm.run_async(&inputs), async { started.elapsed() });
+
+    assert!(outputs.unwrap()[0].error.is_none());
+    assert!(ticked < Duration::from_millis(300));
+}
+
+#[test]
+fn run_report_pairs_inputs_with_outputs() {
+    let server = MockAcm::stations()
+        .with_reply("display station 17571230000", Reply::Disconnect)
+        .start()
+        .unwrap();
+    let report = acm(&server).run_report(&[
+        message("list station", &[]),
+        message("display station 17571230000", &[]),
+    ]);
+
+    assert!(report.connect.is_some() && report.login.is_some());
+    assert_eq!(report.attempts, 1);
+    assert!(report.error.is_some());
+    assert_eq!(report.commands[0].input.command, "list station");
+    assert_eq!(
+        report.commands[0]
+            .output
+            .as_ref()
+            .unwrap()
+            .datas
+            .as_ref()
+            .unwrap()
+            .len(),
+        2
+    );
+    assert_eq!(
+        report.commands[0].bytes_sent,
+        message("list station", &[]).to_string().len()
+    );
+    assert!(report.commands[0].bytes_received > 0);
+    assert!(report.commands[1].output.is_none());
+    assert_eq!(
+        report.bytes_sent(),
+        report.commands.iter().map(|c| c.bytes_sent).sum()
+    );
+}
+
+#[test]
+fn runner_reports_unknown_acms() {
+    let server = MockAcm::stations().start().unwrap();
+    let mut runner = AcmRunner::default();
+    runner
+        .register_acm("CM01", acm(&server))
+        .queue_input("cm-01", &message("list station", &[]))
+        .queue_input("CM01", &message("list station", &[]))
+        .queue_input("XYZZY", &message("list station", &[]));
+
+    let outputs: Vec<_> = runner.run().collect();
+    let unknown: Vec<&UnknownAcm> = outputs
+        .iter()
+        .filter_map(|(_, output)| output.as_ref().err()?.downcast_ref())
+        .collect();
+
+    assert_eq!(outputs.len(), 3);
+    assert_eq!(outputs[0].0, "cm-01");
+    assert!(outputs[1].1.is_ok());
+    assert_eq!(unknown[0].suggestion.as_deref(), Some("CM01"));
+    assert_eq!(unknown[0].inputs.len(), 1);
+    assert_eq!(unknown[1].suggestion, None);
+}
+
+#[test]
+fn registering_acms_keeps_queued_inputs() {
+    let server = MockAcm::stations().start().unwrap();
+    let mut runner = AcmRunner::default();
+    runner
+        .register_acm("CM01", acm(&server))
+        .queue_input("CM02", &message("list station", &[]))
+        .queue_input("CM01", &message("list station", &[]))
+        .queue_input("CM02", &message("list station", &[]))
+        .register_acm("CM02", acm(&server))
+        .register_acm("CM01", acm(&server));
+
+    let plan = runner.plan();
+    let jobs: Vec<(&str, usize)> = plan
+        .jobs
+        .iter()
+        .map(|job| (job.job_name.as_str(), job.inputs.len()))
+        .collect();
+
+    assert_eq!(jobs, [("CM02", 2), ("CM01", 1)]);
+    assert!(plan.unknown.is_empty());
+    let outputs: Vec<_> = runner.run().collect();
+    assert!(outputs.iter().all(|(_, output)| output.is_ok()));
+}
+
+#[test]
+fn runner_expands_groups_and_patterns() {
+    let server = MockAcm::stations().start().unwrap();
+    let logins = Logins::from_reader(
+        "CM01 mock:mock@127.0.0.1:22\n@east = CM01 CM02\n@lab = LAB*\n".as_bytes(),
+    )
+    .unwrap();
+    assert_eq!(logins.acms.len(), 1);
+
+    let mut runner = AcmRunner::default();
+    for name in ["CM01", "CM02", "LAB1", "LAB2"] {
+        runner.register_acm(name, acm(&server));
+    }
+    for (name, members) in logins.groups {
+        runner.register_group(&name, members);
+    }
+    runner
+        .queue_input("@east", &message("list station", &[]))
+        .queue_input("LAB*", &message("list station", &[]))
+        .queue_input("@lab", &message("list station", &[]))
+        .queue_input("@west", &message("list station", &[]));
+
+    let plan = runner.plan();
+    let jobs: Vec<(&str, usize)> = plan
+        .jobs
+        .iter()
+        .map(|job| (job.job_name.as_str(), job.inputs.len()))
+        .collect();
+
+    assert_eq!(jobs, [("CM01", 1), ("CM02", 1), ("LAB1", 2), ("LAB2", 2)]);
+    assert_eq!(plan.unknown[0].job_name, "@west");
+}
+
+#[test]
+fn toml_logins_run_and_report_lines() {
+    let server = MockAcm::stations().start().unwrap();
+    let config = format!(
+        "# Lab ACMs\n[acms.CM01]\nhost = \"{}\"\nport = {}\nuser = \"mock\"\npass = \"mock\"\nhostkey = \"off\"\ntags = [\"east\"]\n",
+        server.addr().ip(),
+        server.addr().port()
+    );
+    let logins = Logins::from_reader(config.as_bytes()).unwrap();
+    let mut runner = AcmRunner::new(logins.acms, Vec::new());
+    for (name, members) in logins.groups {
+        runner.register_group(&name, members);
+    }
+    runner.queue_input("@east", &message("list station", &[]));
+    let outputs: Vec<_> = runner.run().collect();
+
+    assert_eq!(outputs[0].0, "CM01");
+    assert!(outputs[0].1.is_ok());
+    assert_eq!(logins.details[0].1.tags, ["east"]);
+
+    let error = Logins::from_toml("[acms.CM01]\nhost = \"a\"\nuser = \"b\"\n\n[acms.CM02]\nhost = \"c\"\nuser = \"b\"\npass = \"p@ss:word\"\nauth = \"key\"\n")
+        .unwrap_err();
+    assert_eq!(error.to_string(), "Invalid login for CM01 at line 1.");
+
+    let error = Logins::from_toml("[acms.CM01]\nhost = \"a\"\nusr = \"b\"\n").unwrap_err();
+    assert!(format!("{:#}", error).contains("line 3"));
+}
+
+#[test]
+fn passwords_are_looked_up_on_connect() {
+    let server = MockAcm::stations().start().unwrap();
+    let pass = TempFile::new("pass", "").unwrap();
+    let mut from_file = acm(&server);
+    from_file.with_pass_provider(FileSecret {
+        path: pass.path().to_path_buf(),
+    });
+    let mut from_command = acm(&server);
+    from_command.with_pass_provider(CommandSecret {
+        command: "echo mock".into(),
+    });
+    let inputs = [message("list station", &[])];
+
+    // The password isn't written until after the login is configured.
+    assert!(from_file.run(&inputs).is_err());
+    std::fs::write(pass.path(), "mock\n").unwrap();
+
+    assert!(from_file.run(&inputs).is_ok());
+    assert!(from_command.run(&inputs).is_ok());
+    assert_eq!(server.logins(), 2);
+}